@@ -0,0 +1,439 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! A future-based interface to a language server, running on tokio.
+//!
+//! This is only available with the `async` feature. Messages are read and written by tasks
+//! on the current tokio runtime, so no dedicated threads are needed.
+
+use std::future::Future;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use serde_json::value::Value;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::process::Child;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::client::{LanguageServerRef, Notification, RequestHandle, Subscription};
+use crate::error::{ClientError, ResponseError};
+use crate::parsing::{self, ParseError};
+
+/// The write half of an asynchronous connection. Messages are queued and written to the
+/// server by a background task.
+pub struct AsyncWriter(mpsc::UnboundedSender<Vec<u8>>);
+
+impl Write for AsyncWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "writer task has stopped"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Resolves to the `result` member of the server's response, or to its error.
+///
+/// Dropping the future before it resolves cancels the request.
+pub struct ResponseFuture {
+    response: oneshot::Receiver<Result<Value, Value>>,
+    handle: RequestHandle,
+}
+
+impl ResponseFuture {
+    /// The handle of the underlying request.
+    pub fn handle(&self) -> &RequestHandle {
+        &self.handle
+    }
+}
+
+impl Drop for ResponseFuture {
+    fn drop(&mut self) {
+        // a no-op if the request has already completed
+        self.handle.cancel();
+    }
+}
+
+impl Future for ResponseFuture {
+    type Output = Result<Value, ResponseError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let result = match Pin::new(&mut self.response).poll(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(result) => result,
+        };
+        Poll::Ready(match result {
+            Ok(Ok(mut response)) => Ok(response["result"].take()),
+            Ok(Err(response)) => Err(ResponseError::from_error_response(&response)),
+            // the callback was dropped without being called
+            Err(_) => Err(ResponseError::server_disconnected()),
+        })
+    }
+}
+
+/// The notifications sent by the server, in the order they arrived. The stream ends when
+/// the server disconnects.
+pub struct Notifications {
+    receiver: mpsc::UnboundedReceiver<Notification>,
+    _subscription: Subscription,
+}
+
+impl Stream for Notifications {
+    type Item = Notification;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Notification>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// The partial results of a request, in the order they arrived. The stream ends once the
+/// request completes, fails or is cancelled.
+pub struct PartialResults {
+    receiver: mpsc::UnboundedReceiver<Value>,
+}
+
+impl Stream for PartialResults {
+    type Item = Value;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Value>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl<W: 'static + Write + Send> LanguageServerRef<W> {
+    /// Returns a stream of the notifications for `method` sent by the server.
+    pub fn notifications(&self, method: &str) -> Notifications {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let subscription = self.subscribe(method, move |notification| {
+            let _ = tx.send(notification);
+        });
+        Notifications {
+            receiver: rx,
+            _subscription: subscription,
+        }
+    }
+
+    /// Returns a stream of every notification sent by the server.
+    pub fn all_notifications(&self) -> Notifications {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let subscription = self.subscribe_all(move |notification| {
+            let _ = tx.send(notification);
+        });
+        Notifications {
+            receiver: rx,
+            _subscription: subscription,
+        }
+    }
+
+    /// Sends a JSON-RPC request, returning a future that resolves to the server's response.
+    ///
+    /// Errors that prevent the request from being sent are returned immediately.
+    pub fn send_request_async(
+        &self,
+        method: &str,
+        params: &Value,
+    ) -> Result<ResponseFuture, ClientError> {
+        let (tx, rx) = oneshot::channel();
+        let handle = self.send_request(method, params, move |result| {
+            let _ = tx.send(result);
+        })?;
+        Ok(ResponseFuture {
+            response: rx,
+            handle,
+        })
+    }
+
+    /// Sends a JSON-RPC request with a `partialResultToken` added to its params, returning
+    /// a stream of the partial results the server sends back along with a future that
+    /// resolves to its response.
+    ///
+    /// See [`send_request_with_partial_results`](Self::send_request_with_partial_results).
+    pub fn send_request_with_partial_results_async(
+        &self,
+        method: &str,
+        params: &Value,
+    ) -> Result<(PartialResults, ResponseFuture), ClientError> {
+        let (partial_tx, partial_rx) = mpsc::unbounded_channel();
+        let (tx, rx) = oneshot::channel();
+        let handle = self.send_request_with_partial_results(
+            method,
+            params,
+            move |value| {
+                let _ = partial_tx.send(value);
+            },
+            move |result| {
+                let _ = tx.send(result);
+            },
+        )?;
+        let partial_results = PartialResults {
+            receiver: partial_rx,
+        };
+        let response = ResponseFuture {
+            response: rx,
+            handle,
+        };
+        Ok((partial_results, response))
+    }
+}
+
+/// Starts communicating with a language server spawned by tokio, over its stdin and stdout.
+/// The caller keeps the child, and can await its exit. Fails with `InvalidInput` unless both
+/// are piped, in which case the child is left as it was.
+///
+/// Notifications are only kept for streams taken with
+/// [`notifications`](LanguageServerRef::notifications) or
+/// [`all_notifications`](LanguageServerRef::all_notifications); take them before yielding to
+/// the runtime to see the first ones the server sends.
+///
+/// This must be called from within a tokio runtime.
+pub fn start_language_server(child: &mut Child) -> io::Result<LanguageServerRef<AsyncWriter>> {
+    let (mut child_stdin, child_stdout) = match (child.stdin.take(), child.stdout.take()) {
+        (Some(stdin), Some(stdout)) => (stdin, stdout),
+        (stdin, stdout) => {
+            child.stdin = stdin;
+            child.stdout = stdout;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stdio is not piped",
+            ));
+        }
+    };
+
+    let (write_tx, mut write_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let lang_server = LanguageServerRef::new(AsyncWriter(write_tx), None);
+
+    tokio::spawn(async move {
+        while let Some(msg) = write_rx.recv().await {
+            let written = match child_stdin.write_all(&msg).await {
+                Ok(()) => child_stdin.flush().await,
+                Err(err) => Err(err),
+            };
+            if written.is_err() {
+                // dropping the receiver makes further writes fail with BrokenPipe
+                break;
+            }
+        }
+    });
+
+    {
+        let lang_server = lang_server.clone();
+        tokio::spawn(async move {
+            let mut reader = BufReader::new(child_stdout);
+            loop {
+                match parsing::read_message_async(&mut reader).await {
+                    Ok(ref val) => {
+                        if let Err(err) = lang_server.handle_msg(val) {
+                            lang_server.report_error(err);
+                        }
+                    }
+                    Err(ParseError::Empty) => break,
+                    Err(ParseError::Io(err)) => {
+                        lang_server.report_error(ClientError::Io(err));
+                        break;
+                    }
+                    Err(err) => lang_server.report_error(ClientError::Parse(err)),
+                }
+            }
+            lang_server.handle_disconnect();
+        });
+    }
+
+    Ok(lang_server)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+    use std::process::Stdio;
+
+    use tokio::process::Command;
+
+    use super::*;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    async fn next_notification(notifications: &mut Notifications) -> Option<Notification> {
+        poll_fn(|cx| Pin::new(&mut *notifications).poll_next(cx)).await
+    }
+
+    #[test]
+    fn test_unpiped_child() {
+        block_on(async {
+            let mut child = Command::new("cat")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap();
+            let err = start_language_server(&mut child).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            // the child keeps its stdin, so closing it lets cat exit
+            assert!(child.stdin.take().is_some());
+            child.wait().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_async_handshake() {
+        block_on(async {
+            let mut child = Command::new("rust-analyzer")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("failed to start language server");
+            let lang_server = start_language_server(&mut child).unwrap();
+
+            let init = json!({"processId": null, "rootUri": null, "capabilities": {}});
+            let result = lang_server
+                .send_request_async("initialize", &init)
+                .unwrap()
+                .await
+                .expect("initialize failed");
+            assert!(result.get("capabilities").is_some());
+            lang_server
+                .send_notification("initialized", &json!({}))
+                .unwrap();
+
+            // should be null, not a map
+            let error = lang_server
+                .send_request_async("shutdown", &json!({}))
+                .unwrap()
+                .await
+                .unwrap_err();
+            println!("received error {error}");
+
+            let result = lang_server
+                .send_request_async("shutdown", &json!(null))
+                .unwrap()
+                .await;
+            assert_eq!(result, Ok(Value::Null));
+            lang_server.send_notification("exit", &json!(null)).unwrap();
+            child.wait().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_async_notifications_and_disconnect() {
+        block_on(async {
+            // announces itself, then exits after reading the start of our request
+            let script = r#"printf 'Content-Length: 81\r\n\r\n{"jsonrpc":"2.0","method":"window/logMessage","params":{"type":3,"message":"hi"}}'; head -c 1 > /dev/null"#;
+            let mut child = Command::new("sh")
+                .args(["-c", script])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let lang_server = start_language_server(&mut child).unwrap();
+            let mut notifications = lang_server.all_notifications();
+
+            let notification = next_notification(&mut notifications).await.unwrap();
+            assert_eq!(notification.method, "window/logMessage");
+            assert_eq!(notification.params["message"], "hi");
+
+            let error = lang_server
+                .send_request_async("initialize", &json!({}))
+                .unwrap()
+                .await
+                .unwrap_err();
+            assert_eq!(error, ResponseError::server_disconnected());
+            child.wait().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn test_partial_results_stream() {
+        block_on(async {
+            let buffer = crate::client::tests::SharedBuffer::default();
+            let lang_server = LanguageServerRef::new(buffer.clone(), None);
+            let (mut partial_results, response) = lang_server
+                .send_request_with_partial_results_async("workspace/symbol", &json!({"query": ""}))
+                .unwrap();
+            let request = buffer.messages().pop().unwrap();
+            let progress = json!({
+                "jsonrpc": "2.0",
+                "method": "$/progress",
+                "params": {"token": request["params"]["partialResultToken"], "value": [1]},
+            });
+            lang_server.handle_msg(&progress).unwrap();
+            lang_server
+                .handle_msg(&json!({"jsonrpc": "2.0", "id": request["id"], "result": [2]}))
+                .unwrap();
+
+            let first = poll_fn(|cx| Pin::new(&mut partial_results).poll_next(cx)).await;
+            assert_eq!(first, Some(json!([1])));
+            let last = poll_fn(|cx| Pin::new(&mut partial_results).poll_next(cx)).await;
+            assert_eq!(last, None);
+            assert_eq!(response.await, Ok(json!([2])));
+        });
+    }
+
+    #[test]
+    fn test_dropping_future_cancels_request() {
+        block_on(async {
+            // reads requests without ever answering
+            let mut child = Command::new("sh")
+                .args(["-c", "cat > /dev/null"])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let lang_server = start_language_server(&mut child).unwrap();
+
+            let (tx, rx) = std::sync::mpsc::channel();
+            lang_server
+                .send_request("textDocument/hover", &json!({}), move |result| {
+                    let _ = tx.send(result);
+                })
+                .unwrap();
+            let future = lang_server
+                .send_request_async("textDocument/completion", &json!({}))
+                .unwrap();
+            let handle = future.handle().clone();
+            drop(future);
+
+            // the dropped request is gone; the other is unaffected
+            assert_eq!(handle.id(), 2);
+            lang_server
+                .handle_msg(&json!({"jsonrpc": "2.0", "id": 1, "result": null}))
+                .unwrap();
+            assert!(rx.recv().unwrap().is_ok());
+            assert!(lang_server
+                .handle_msg(&json!({"jsonrpc": "2.0", "id": 2, "result": null}))
+                .is_ok());
+            assert!(lang_server
+                .handle_msg(&json!({"jsonrpc": "2.0", "id": 2, "result": null}))
+                .is_err());
+            child.kill().await.unwrap();
+        });
+    }
+}
@@ -0,0 +1,557 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! Tracking what the server and client are each able to do.
+
+use std::io::Write;
+
+use lsp_types::{
+    ClientCapabilities, CodeActionClientCapabilities, CompletionClientCapabilities,
+    DiagnosticClientCapabilities, DiagnosticTag, DiagnosticWorkspaceClientCapabilities,
+    DidChangeWatchedFilesClientCapabilities, DocumentSymbolClientCapabilities,
+    DynamicRegistrationClientCapabilities, GeneralClientCapabilities, GotoCapability,
+    HoverClientCapabilities, MarkupKind, PublishDiagnosticsClientCapabilities, Registration,
+    RenameClientCapabilities, ResourceOperationKind, ServerCapabilities,
+    SignatureHelpClientCapabilities, TagSupport, TextDocumentClientCapabilities,
+    TextDocumentSyncClientCapabilities, WindowClientCapabilities, WorkspaceClientCapabilities,
+    WorkspaceEditClientCapabilities,
+};
+use serde_json::value::Value;
+
+use crate::client::LanguageServerRef;
+use crate::position::PositionEncoding;
+
+/// Requests that a server only accepts if it advertises them, and the member of
+/// `ServerCapabilities` (or the `registerOptions` of a dynamic registration) that does so.
+const PROVIDERS: &[(&str, &str)] = &[
+    ("textDocument/hover", "hoverProvider"),
+    ("textDocument/completion", "completionProvider"),
+    ("textDocument/signatureHelp", "signatureHelpProvider"),
+    ("textDocument/declaration", "declarationProvider"),
+    ("textDocument/definition", "definitionProvider"),
+    ("textDocument/typeDefinition", "typeDefinitionProvider"),
+    ("textDocument/implementation", "implementationProvider"),
+    ("textDocument/references", "referencesProvider"),
+    (
+        "textDocument/documentHighlight",
+        "documentHighlightProvider",
+    ),
+    ("textDocument/documentSymbol", "documentSymbolProvider"),
+    ("textDocument/codeAction", "codeActionProvider"),
+    ("textDocument/codeLens", "codeLensProvider"),
+    ("textDocument/documentLink", "documentLinkProvider"),
+    ("textDocument/documentColor", "colorProvider"),
+    ("textDocument/colorPresentation", "colorProvider"),
+    ("textDocument/formatting", "documentFormattingProvider"),
+    (
+        "textDocument/rangeFormatting",
+        "documentRangeFormattingProvider",
+    ),
+    (
+        "textDocument/onTypeFormatting",
+        "documentOnTypeFormattingProvider",
+    ),
+    ("textDocument/rename", "renameProvider"),
+    ("textDocument/prepareRename", "renameProvider"),
+    ("textDocument/foldingRange", "foldingRangeProvider"),
+    ("textDocument/selectionRange", "selectionRangeProvider"),
+    ("textDocument/prepareCallHierarchy", "callHierarchyProvider"),
+    ("textDocument/semanticTokens", "semanticTokensProvider"),
+    ("textDocument/semanticTokens/full", "semanticTokensProvider"),
+    (
+        "textDocument/semanticTokens/full/delta",
+        "semanticTokensProvider",
+    ),
+    (
+        "textDocument/semanticTokens/range",
+        "semanticTokensProvider",
+    ),
+    (
+        "textDocument/linkedEditingRange",
+        "linkedEditingRangeProvider",
+    ),
+    ("textDocument/moniker", "monikerProvider"),
+    ("textDocument/inlayHint", "inlayHintProvider"),
+    ("textDocument/inlineValue", "inlineValueProvider"),
+    ("textDocument/diagnostic", "diagnosticProvider"),
+    ("workspace/diagnostic", "diagnosticProvider"),
+    ("workspace/symbol", "workspaceSymbolProvider"),
+    ("workspace/executeCommand", "executeCommandProvider"),
+];
+
+/// Returns the member of `ServerCapabilities` that advertises support for `method`, if the
+/// method needs to be advertised at all.
+fn provider_for(method: &str) -> Option<&'static str> {
+    PROVIDERS
+        .iter()
+        .find(|(m, _)| *m == method)
+        .map(|(_, provider)| *provider)
+}
+
+/// The server's capabilities: those it returned from `initialize`, updated by any dynamic
+/// registrations since.
+#[derive(Debug, Default)]
+pub(crate) struct ServerCapabilityState {
+    initial: Option<Value>,
+    registrations: Vec<Registration>,
+    effective: Option<(ServerCapabilities, Value)>,
+}
+
+impl ServerCapabilityState {
+    /// Records the capabilities returned from `initialize`.
+    pub(crate) fn set_initial(&mut self, capabilities: Value) {
+        self.initial = Some(capabilities);
+        self.recompute();
+    }
+
+    pub(crate) fn register(&mut self, registrations: Vec<Registration>) {
+        for registration in registrations {
+            self.registrations.retain(|r| r.id != registration.id);
+            self.registrations.push(registration);
+        }
+        self.recompute();
+    }
+
+    pub(crate) fn unregister(&mut self, ids: &[String]) {
+        self.registrations.retain(|r| !ids.contains(&r.id));
+        self.recompute();
+    }
+
+    pub(crate) fn capabilities(&self) -> Option<&ServerCapabilities> {
+        self.effective
+            .as_ref()
+            .map(|(capabilities, _)| capabilities)
+    }
+
+    pub(crate) fn registrations(&self) -> &[Registration] {
+        &self.registrations
+    }
+
+    /// Whether requests for `method` can be sent. Methods that servers don't advertise are
+    /// always supported; others are not, until the capabilities are known.
+    pub(crate) fn supports(&self, method: &str) -> bool {
+        let provider = match provider_for(method) {
+            Some(provider) => provider,
+            None => return true,
+        };
+        match self.effective {
+            Some((_, ref json)) => !matches!(
+                json.get(provider),
+                None | Some(Value::Null) | Some(Value::Bool(false))
+            ),
+            None => false,
+        }
+    }
+
+    /// Whether the capabilities have been received yet.
+    pub(crate) fn is_known(&self) -> bool {
+        self.effective.is_some()
+    }
+
+    fn recompute(&mut self) {
+        let mut json = match self.initial {
+            Some(ref initial) => initial.clone(),
+            None => return,
+        };
+        if !json.is_object() {
+            json = json!({});
+        }
+        for registration in &self.registrations {
+            let provider = match provider_for(&registration.method) {
+                Some(provider) => provider,
+                None => continue,
+            };
+            // the options may not fit the member's type, in which case the plain
+            // `true` form is the best we can do
+            let candidates = registration
+                .register_options
+                .iter()
+                .cloned()
+                .chain(Some(Value::Bool(true)));
+            for options in candidates {
+                let mut updated = json.clone();
+                updated[provider] = options;
+                if serde_json::from_value::<ServerCapabilities>(updated.clone()).is_ok() {
+                    json = updated;
+                    break;
+                }
+            }
+        }
+        self.effective = serde_json::from_value(json.clone())
+            .ok()
+            .map(|capabilities| (capabilities, json));
+    }
+}
+
+/// Builds the `ClientCapabilities` sent with `initialize`.
+///
+/// The defaults describe what this crate handles on its own, such as diagnostics with tags
+/// and versions. Applying workspace edits and work done progress are off, since they need a
+/// [`WorkspaceEditor`](crate::WorkspaceEditor) or a [`ProgressTracker`](crate::ProgressTracker)
+/// to answer the server; [`for_server`](Self::for_server) turns them on when one is attached.
+/// Dynamic registration and watched files are off until the caller opts in. Each capability
+/// can be toggled.
+///
+/// ```
+/// use lsp_client::capabilities::ClientCapabilitiesBuilder;
+///
+/// let capabilities = ClientCapabilitiesBuilder::new()
+///     .work_done_progress(true)
+///     .build();
+/// assert_eq!(capabilities.window.unwrap().work_done_progress, Some(true));
+/// assert_eq!(capabilities.workspace.unwrap().apply_edit, Some(false));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientCapabilitiesBuilder {
+    apply_edit: bool,
+    workspace_edit: bool,
+    work_done_progress: bool,
+    dynamic_registration: bool,
+    watched_files: bool,
+    diagnostic_tags: Vec<DiagnosticTag>,
+    position_encodings: Vec<PositionEncoding>,
+    pull_diagnostics: bool,
+    experimental: Option<Value>,
+}
+
+impl Default for ClientCapabilitiesBuilder {
+    fn default() -> Self {
+        ClientCapabilitiesBuilder {
+            apply_edit: false,
+            workspace_edit: false,
+            work_done_progress: false,
+            dynamic_registration: false,
+            watched_files: false,
+            diagnostic_tags: vec![DiagnosticTag::UNNECESSARY, DiagnosticTag::DEPRECATED],
+            position_encodings: vec![
+                PositionEncoding::Utf8,
+                PositionEncoding::Utf16,
+                PositionEncoding::Utf32,
+            ],
+            pull_diagnostics: false,
+            experimental: None,
+        }
+    }
+}
+
+impl ClientCapabilitiesBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The defaults, along with the capabilities of the handlers registered on `server`:
+    /// applying workspace edits if it answers `workspace/applyEdit`, as a
+    /// [`WorkspaceEditor`](crate::WorkspaceEditor) does, and work done progress if it answers
+    /// `window/workDoneProgress/create`, as a [`ProgressTracker`](crate::ProgressTracker)
+    /// does. Attach them before calling this.
+    pub fn for_server<W: 'static + Write + Send>(server: &LanguageServerRef<W>) -> Self {
+        let apply_edit = server.handles_request("workspace/applyEdit");
+        Self::new()
+            .apply_edit(apply_edit)
+            .workspace_edit(apply_edit)
+            .work_done_progress(server.handles_request("window/workDoneProgress/create"))
+    }
+
+    /// Whether the client applies workspace edits requested with `workspace/applyEdit`.
+    pub fn apply_edit(mut self, enabled: bool) -> Self {
+        self.apply_edit = enabled;
+        self
+    }
+
+    /// Whether the client applies the `documentChanges` of workspace edits, including
+    /// creating, renaming and deleting files, as a [`WorkspaceEditor`](crate::WorkspaceEditor)
+    /// does. Without it, servers send only `changes`.
+    pub fn workspace_edit(mut self, enabled: bool) -> Self {
+        self.workspace_edit = enabled;
+        self
+    }
+
+    /// Whether the server may create work done progress with
+    /// `window/workDoneProgress/create`.
+    pub fn work_done_progress(mut self, enabled: bool) -> Self {
+        self.work_done_progress = enabled;
+        self
+    }
+
+    /// Whether the server may register the text document features, configuration and
+    /// commands dynamically.
+    pub fn dynamic_registration(mut self, enabled: bool) -> Self {
+        self.dynamic_registration = enabled;
+        self
+    }
+
+    /// Whether the caller watches the files the server registers interest in, and sends
+    /// `workspace/didChangeWatchedFiles`. Servers that are offered this may stop watching
+    /// files themselves.
+    pub fn watched_files(mut self, enabled: bool) -> Self {
+        self.watched_files = enabled;
+        self
+    }
+
+    /// The diagnostic tags the client understands. Tag support is not advertised if this is
+    /// empty.
+    pub fn diagnostic_tags(mut self, tags: Vec<DiagnosticTag>) -> Self {
+        self.diagnostic_tags = tags;
+        self
+    }
+
+    /// The position encodings the client accepts, most preferred first. The server falls
+    /// back to UTF-16 if it supports none of them.
+    pub fn position_encodings(mut self, encodings: Vec<PositionEncoding>) -> Self {
+        self.position_encodings = encodings;
+        self
+    }
+
+    /// Whether the client pulls diagnostics, as a
+    /// [`DiagnosticPuller`](crate::diagnostics::DiagnosticPuller) does. Off by default, since
+    /// some servers stop publishing diagnostics to clients that can pull them.
+    pub fn pull_diagnostics(mut self, enabled: bool) -> Self {
+        self.pull_diagnostics = enabled;
+        self
+    }
+
+    /// Server-specific capabilities, sent as `experimental`.
+    pub fn experimental(mut self, experimental: Value) -> Self {
+        self.experimental = Some(experimental);
+        self
+    }
+
+    pub fn build(self) -> ClientCapabilities {
+        let dynamic_registration = Some(self.dynamic_registration);
+        let registration = DynamicRegistrationClientCapabilities {
+            dynamic_registration,
+        };
+        let goto = GotoCapability {
+            dynamic_registration,
+            link_support: Some(true),
+        };
+        let markup = vec![MarkupKind::Markdown, MarkupKind::PlainText];
+        let tag_support = if self.diagnostic_tags.is_empty() {
+            None
+        } else {
+            Some(TagSupport {
+                value_set: self.diagnostic_tags,
+            })
+        };
+
+        let workspace = WorkspaceClientCapabilities {
+            apply_edit: Some(self.apply_edit),
+            workspace_edit: self
+                .workspace_edit
+                .then(|| WorkspaceEditClientCapabilities {
+                    document_changes: Some(true),
+                    resource_operations: Some(vec![
+                        ResourceOperationKind::Create,
+                        ResourceOperationKind::Rename,
+                        ResourceOperationKind::Delete,
+                    ]),
+                    ..Default::default()
+                }),
+            did_change_configuration: Some(registration),
+            did_change_watched_files: self.watched_files.then_some(
+                DidChangeWatchedFilesClientCapabilities {
+                    dynamic_registration: Some(true),
+                    relative_pattern_support: None,
+                },
+            ),
+            execute_command: Some(registration),
+            diagnostic: self
+                .pull_diagnostics
+                .then_some(DiagnosticWorkspaceClientCapabilities {
+                    refresh_support: Some(true),
+                }),
+            ..Default::default()
+        };
+        let text_document = TextDocumentClientCapabilities {
+            synchronization: Some(TextDocumentSyncClientCapabilities {
+                dynamic_registration,
+                ..Default::default()
+            }),
+            completion: Some(CompletionClientCapabilities {
+                dynamic_registration,
+                ..Default::default()
+            }),
+            hover: Some(HoverClientCapabilities {
+                dynamic_registration,
+                content_format: Some(markup),
+            }),
+            signature_help: Some(SignatureHelpClientCapabilities {
+                dynamic_registration,
+                ..Default::default()
+            }),
+            declaration: Some(goto),
+            definition: Some(goto),
+            type_definition: Some(goto),
+            implementation: Some(goto),
+            references: Some(registration),
+            document_highlight: Some(registration),
+            document_symbol: Some(DocumentSymbolClientCapabilities {
+                dynamic_registration,
+                hierarchical_document_symbol_support: Some(true),
+                ..Default::default()
+            }),
+            code_action: Some(CodeActionClientCapabilities {
+                dynamic_registration,
+                ..Default::default()
+            }),
+            formatting: Some(registration),
+            range_formatting: Some(registration),
+            rename: Some(RenameClientCapabilities {
+                dynamic_registration,
+                ..Default::default()
+            }),
+            publish_diagnostics: Some(PublishDiagnosticsClientCapabilities {
+                related_information: Some(true),
+                tag_support,
+                version_support: Some(true),
+                ..Default::default()
+            }),
+            diagnostic: self
+                .pull_diagnostics
+                .then_some(DiagnosticClientCapabilities {
+                    dynamic_registration,
+                    related_document_support: Some(true),
+                }),
+            ..Default::default()
+        };
+        let window = WindowClientCapabilities {
+            work_done_progress: Some(self.work_done_progress),
+            ..Default::default()
+        };
+
+        let general = GeneralClientCapabilities {
+            position_encodings: Some(
+                self.position_encodings
+                    .into_iter()
+                    .map(PositionEncoding::to_kind)
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+
+        ClientCapabilities {
+            workspace: Some(workspace),
+            text_document: Some(text_document),
+            window: Some(window),
+            general: Some(general),
+            experimental: self.experimental,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registration(id: &str, method: &str, options: Option<Value>) -> Registration {
+        Registration {
+            id: id.to_owned(),
+            method: method.to_owned(),
+            register_options: options,
+        }
+    }
+
+    #[test]
+    fn test_dynamic_registration() {
+        let mut state = ServerCapabilityState::default();
+        assert!(!state.supports("textDocument/hover"));
+        assert!(state.supports("workspace/configuration"));
+
+        state.set_initial(json!({"hoverProvider": true, "renameProvider": false}));
+        assert!(state.supports("textDocument/hover"));
+        assert!(!state.supports("textDocument/rename"));
+        assert!(!state.supports("textDocument/completion"));
+
+        state.register(vec![
+            registration(
+                "1",
+                "textDocument/completion",
+                Some(json!({"documentSelector": null, "triggerCharacters": ["."]})),
+            ),
+            registration("2", "textDocument/rename", None),
+            registration("3", "workspace/didChangeWatchedFiles", None),
+        ]);
+        assert!(state.supports("textDocument/completion"));
+        assert!(state.supports("textDocument/rename"));
+        let capabilities = state.capabilities().unwrap();
+        let completion = capabilities.completion_provider.as_ref().unwrap();
+        assert_eq!(completion.trigger_characters, Some(vec![".".to_owned()]));
+        assert_eq!(state.registrations().len(), 3);
+
+        state.unregister(&["1".to_owned(), "2".to_owned()]);
+        assert!(!state.supports("textDocument/completion"));
+        assert!(!state.supports("textDocument/rename"));
+        assert!(state.supports("textDocument/hover"));
+        assert_eq!(state.registrations().len(), 1);
+    }
+
+    #[test]
+    fn test_client_capabilities_toggles() {
+        let defaults = serde_json::to_value(ClientCapabilitiesBuilder::new().build()).unwrap();
+        assert_eq!(defaults["workspace"]["applyEdit"], false);
+        assert!(defaults["workspace"].get("workspaceEdit").is_none());
+        assert_eq!(defaults["window"]["workDoneProgress"], false);
+        assert!(defaults["textDocument"]["synchronization"]
+            .get("didSave")
+            .is_none());
+        assert_eq!(
+            defaults["textDocument"]["hover"]["dynamicRegistration"],
+            false
+        );
+        assert!(defaults["workspace"].get("didChangeWatchedFiles").is_none());
+        assert_eq!(
+            defaults["textDocument"]["publishDiagnostics"]["tagSupport"]["valueSet"],
+            json!([1, 2])
+        );
+        assert_eq!(
+            defaults["general"]["positionEncodings"],
+            json!(["utf-8", "utf-16", "utf-32"])
+        );
+
+        let toggled = ClientCapabilitiesBuilder::new()
+            .apply_edit(true)
+            .workspace_edit(true)
+            .work_done_progress(true)
+            .dynamic_registration(true)
+            .watched_files(true)
+            .diagnostic_tags(Vec::new())
+            .build();
+        let toggled = serde_json::to_value(toggled).unwrap();
+        assert_eq!(toggled["workspace"]["applyEdit"], true);
+        assert_eq!(
+            toggled["workspace"]["workspaceEdit"]["documentChanges"],
+            true
+        );
+        assert_eq!(toggled["window"]["workDoneProgress"], true);
+        assert_eq!(
+            toggled["textDocument"]["hover"]["dynamicRegistration"],
+            true
+        );
+        assert_eq!(
+            toggled["workspace"]["didChangeWatchedFiles"]["dynamicRegistration"],
+            true
+        );
+        assert!(toggled["textDocument"]["publishDiagnostics"]
+            .get("tagSupport")
+            .is_none());
+    }
+}
@@ -20,16 +20,25 @@
 //OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 //SOFTWARE.
 
-use std::collections::HashMap;
-use std::io::{BufReader, Write};
-use std::process::{Child, ChildStdin};
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ExitStatus};
+use std::sync::{mpsc, Arc, Mutex, Weak};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use jsonrpc_lite::JsonRpc as JsonRPC;
 use serde_json::{self, value::Value};
 
+use lsp_types::request::{RegisterCapability, UnregisterCapability};
+use lsp_types::{ProgressToken, Registration, ServerCapabilities};
+
+use crate::capabilities::ServerCapabilityState;
+use crate::error::{ClientError, ResponseError};
 use crate::parsing::{self, ParseError};
+use crate::position::PositionEncoding;
+use crate::timer;
+use crate::transport;
 
 // this to get around some type system pain related to callbacks. See:
 // https://doc.rust-lang.org/beta/book/trait-objects.html,
@@ -46,6 +55,37 @@ impl<F: Send + FnOnce(Result<Value, Value>)> Callable for F {
 
 type Callback = Box<dyn Callable>;
 
+/// A notification sent by the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub method: String,
+    pub params: Value,
+}
+
+type NotificationHandler = Arc<dyn Fn(Notification) + Send + Sync>;
+
+/// A notification handler, along with the method it is interested in.
+struct Subscriber {
+    id: usize,
+    /// `None` for subscribers that receive every notification.
+    method: Option<String>,
+    handler: NotificationHandler,
+}
+
+/// Answers a request sent by the server, given its params.
+type RequestHandler = Arc<dyn Fn(Value) -> Result<Value, ResponseError> + Send + Sync>;
+
+/// Receives errors that occur outside of any caller's control, such as a malformed message
+/// arriving on the reader thread.
+type ErrorSink = Arc<dyn Fn(ClientError) + Send + Sync>;
+
+/// Called with the method and params of every request, or every notification, just before
+/// it is sent.
+type MessageHook = Arc<dyn Fn(&str, &Value) + Send + Sync>;
+
+/// Receives the value of each `$/progress` notification carrying a partial result.
+type PartialResultHandler = Arc<dyn Fn(Value) + Send + Sync>;
+
 /// Represents (and mediates communcation with) a Language Server.
 ///
 /// LanguageServer should only ever be instantiated or accessed through an instance of
@@ -54,150 +94,816 @@ struct LanguageServer<W: Write> {
     peer: W,
     pending: HashMap<usize, Callback>,
     next_id: usize,
-}
-
-/// Generates a Language Server Protocol compliant message.
-fn prepare_lsp_json(msg: &Value) -> Result<String, serde_json::error::Error> {
-    let request = serde_json::to_string(&msg)?;
-    Ok(format!(
-        "Content-Length: {}\r\n\r\n{}",
-        request.len(),
-        request
-    ))
+    /// Requests we have given up on; responses that still arrive for them are dropped. Only
+    /// the most recent `MAX_CANCELLED` are remembered, since the server may never answer.
+    cancelled: BTreeSet<usize>,
+    /// Applied to requests that are sent without an explicit timeout.
+    default_timeout: Option<Duration>,
+    /// Set once the server's output has closed; no further responses will arrive.
+    disconnected: bool,
+    /// The server process, if this client spawned it.
+    process: Option<Child>,
+    exit_status: Option<ExitStatus>,
+    error_sink: ErrorSink,
+    /// Handlers for requests sent by the server, keyed by method.
+    request_handlers: HashMap<String, RequestHandler>,
+    request_hooks: Vec<MessageHook>,
+    notification_hooks: Vec<MessageHook>,
+    subscribers: Vec<Subscriber>,
+    next_subscriber_id: usize,
+    /// Handlers for the partial results of pending requests, keyed by `partialResultToken`.
+    partial_results: HashMap<ProgressToken, PartialResultHandler>,
+    next_partial_result_token: usize,
+    capabilities: ServerCapabilityState,
+    /// Whether requests the server has not advertised support for are rejected locally.
+    check_capabilities: bool,
 }
 
 #[allow(dead_code)]
 impl<W: Write> LanguageServer<W> {
-    fn write(&mut self, msg: &str) {
-        self.peer
-            .write_all(msg.as_bytes())
-            .expect("error writing to stdin");
-        self.peer.flush().expect("error flushing child stdin");
+    fn write(&mut self, msg: &str) -> Result<(), ClientError> {
+        self.peer.write_all(msg.as_bytes())?;
+        self.peer.flush()?;
+        Ok(())
     }
 
-    fn send_request(&mut self, method: &str, params: &Value, completion: Callback) {
+    /// Sends a request, returning the id it was assigned.
+    fn send_request(
+        &mut self,
+        method: &str,
+        params: &Value,
+        completion: Callback,
+    ) -> Result<usize, ClientError> {
+        if self.disconnected {
+            return Err(ClientError::Disconnected);
+        }
+        if self.check_capabilities
+            && self.capabilities.is_known()
+            && !self.capabilities.supports(method)
+        {
+            return Err(ClientError::Unsupported(method.to_owned()));
+        }
+
+        let id = self.next_id;
         let request = json!({
             "jsonrpc": "2.0",
-            "id": self.next_id,
+            "id": id,
             "method": method,
             "params": params
         });
 
-        self.pending.insert(self.next_id, completion);
         self.next_id += 1;
-        self.send_rpc(&request);
+        self.send_rpc(&request)?;
+        self.pending.insert(id, completion);
+        Ok(id)
     }
 
-    fn send_notification(&mut self, method: &str, params: &Value) {
+    fn send_notification(&mut self, method: &str, params: &Value) -> Result<(), ClientError> {
+        if self.disconnected {
+            return Err(ClientError::Disconnected);
+        }
+
         let notification = json!({
             "jsonrpc": "2.0",
             "method": method,
             "params": params
         });
-        self.send_rpc(&notification);
+        self.send_rpc(&notification)
+    }
+
+    /// Removes and returns the callback for the request with the given id, or `None` if the
+    /// request was cancelled and the response should be dropped.
+    fn take_callback(&mut self, id: &Value) -> Result<Option<Callback>, ClientError> {
+        let unknown = || ClientError::UnknownResponseId(id.clone());
+        let id = number_from_id(id).ok_or_else(unknown)?;
+        if let Some(callback) = self.pending.remove(&id) {
+            return Ok(Some(callback));
+        }
+        if self.cancelled.remove(&id) {
+            return Ok(None);
+        }
+        Err(unknown())
     }
 
-    fn handle_response(&mut self, id: usize, result: Value) {
-        let callback = self
-            .pending
-            .remove(&id)
-            .unwrap_or_else(|| panic!("id {} missing from request table", id));
-        callback.call(Ok(result));
+    /// Stops waiting for the request with the given id and asks the server to abandon it,
+    /// returning the request's callback if it was still pending.
+    fn cancel_request(&mut self, id: usize) -> Option<Callback> {
+        let callback = self.pending.remove(&id)?;
+        self.cancelled.insert(id);
+        if self.cancelled.len() > MAX_CANCELLED {
+            // ids only grow, so this forgets the request given up on longest ago
+            self.cancelled.pop_first();
+        }
+        // if this fails the server is gone, and won't be answering anyway
+        let _ = self.send_notification("$/cancelRequest", &json!({ "id": id }));
+        Some(callback)
     }
 
-    fn handle_error(&mut self, id: usize, error: Value) {
-        let callback = self
-            .pending
-            .remove(&id)
-            .unwrap_or_else(|| panic!("id {} missing from request table", id));
-        callback.call(Err(error));
+    /// Marks the server as gone, returning every callback still awaiting a response.
+    fn disconnect(&mut self) -> Vec<(usize, Callback)> {
+        self.disconnected = true;
+        // dropping the handlers closes any channels they deliver to
+        self.subscribers.clear();
+        self.partial_results.clear();
+        self.pending.drain().collect()
+    }
+
+    /// Checks whether the server process has exited, without blocking.
+    fn poll_exit_status(&mut self) -> Option<ExitStatus> {
+        if self.exit_status.is_none() {
+            if let Some(ref mut child) = self.process {
+                self.exit_status = child.try_wait().ok().flatten();
+            }
+        }
+        self.exit_status
     }
 
-    fn send_rpc(&mut self, rpc: &Value) {
-        let rpc = match prepare_lsp_json(rpc) {
-            Ok(r) => r,
-            Err(err) => panic!("error encoding rpc {:?}", err),
+    /// Answers a request sent by the server.
+    fn send_response(
+        &mut self,
+        id: &Value,
+        result: Result<Value, ResponseError>,
+    ) -> Result<(), ClientError> {
+        let response = match result {
+            Ok(result) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": result,
+            }),
+            Err(error) => error.to_response(id),
         };
-        self.write(&rpc);
+        self.send_rpc(&response)
+    }
+
+    fn send_rpc(&mut self, rpc: &Value) -> Result<(), ClientError> {
+        let rpc = parsing::prepare_lsp_json(rpc)?;
+        self.write(&rpc)
+    }
+}
+
+/// Lets a request be abandoned without knowing the type of the server's writer.
+trait Abandon: Send + Sync {
+    /// Cancels the request with the given id if it is still pending, completing its callback
+    /// with `error`.
+    fn abandon(&self, id: usize, error: ResponseError);
+}
+
+impl<W: 'static + Write + Send> Abandon for Mutex<LanguageServer<W>> {
+    fn abandon(&self, id: usize, error: ResponseError) {
+        let callback = self.lock().unwrap().cancel_request(id);
+        if let Some(callback) = callback {
+            callback.call(Err(error.to_response(&json!(id))));
+        }
+    }
+}
+
+/// Identifies an in-flight request, and allows it to be cancelled.
+#[derive(Clone)]
+pub struct RequestHandle {
+    id: usize,
+    server: Weak<dyn Abandon>,
+}
+
+impl RequestHandle {
+    /// The JSON-RPC id of the request.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Cancels the request if it is still pending: `$/cancelRequest` is sent to the server,
+    /// the request's callback receives an error response whose code is
+    /// [`codes::REQUEST_CANCELLED`](crate::error::codes::REQUEST_CANCELLED), and the
+    /// server's response is discarded if it arrives later.
+    ///
+    /// Does nothing if the request has already completed.
+    pub fn cancel(&self) {
+        if let Some(server) = self.server.upgrade() {
+            server.abandon(self.id, ResponseError::request_cancelled());
+        }
+    }
+}
+
+impl std::fmt::Debug for RequestHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RequestHandle")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+/// Lets a subscription be removed without knowing the type of the server's writer.
+pub(crate) trait Unsubscribe: Send + Sync {
+    fn unsubscribe(&self, id: usize);
+}
+
+impl<W: 'static + Write + Send> Unsubscribe for Mutex<LanguageServer<W>> {
+    fn unsubscribe(&self, id: usize) {
+        // take the handler out so that it is dropped after the lock is released
+        let removed = {
+            let mut inner = self.lock().unwrap();
+            let index = inner.subscribers.iter().position(|sub| sub.id == id);
+            index.map(|index| inner.subscribers.remove(index))
+        };
+        drop(removed);
+    }
+}
+
+/// Keeps a handler registered, such as for notifications; dropping it removes the handler.
+pub struct Subscription {
+    id: usize,
+    server: Weak<dyn Unsubscribe>,
+    detached: bool,
+}
+
+impl Subscription {
+    pub(crate) fn new(id: usize, server: Weak<dyn Unsubscribe>) -> Self {
+        Subscription {
+            id,
+            server,
+            detached: false,
+        }
+    }
+
+    /// Keeps the handler registered for as long as the server is connected.
+    pub fn detach(mut self) {
+        self.detached = true;
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if self.detached {
+            return;
+        }
+        if let Some(server) = self.server.upgrade() {
+            server.unsubscribe(self.id);
+        }
+    }
+}
+
+impl std::fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Subscription")
+            .field("id", &self.id)
+            .finish()
     }
 }
 
 /// Access control and convenience wrapper around a shared LanguageServer instance.
+///
+/// Request handlers and notification subscribers are run on the thread that reads from the
+/// server, so they must not block waiting for the server to respond to a request.
 pub struct LanguageServerRef<W: Write>(Arc<Mutex<LanguageServer<W>>>);
 
-//FIXME: this is hacky, and prevents good error propagation,
-fn number_from_id(id: Option<&Value>) -> usize {
-    let id = id.expect("response missing id field");
+/// How often `wait_for_exit` checks whether the server process has exited.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How many abandoned requests to remember. A response that arrives after its request has
+/// been forgotten is reported as having an unknown id.
+const MAX_CANCELLED: usize = 1024;
+
+/// Interprets a response id as one of our request ids. We only ever send numeric ids, but
+/// some servers echo them back as strings.
+fn number_from_id(id: &Value) -> Option<usize> {
     let id = match id {
-        Value::Number(n) => n.as_u64().expect("failed to take id as u64"),
-        Value::String(s) => s.parse().expect("failed to convert string id to u64"),
-        other => panic!("unexpected value for id field: {:?}", other),
+        Value::Number(n) => n.as_u64()?,
+        Value::String(s) => s.parse().ok()?,
+        _ => return None,
     };
 
-    id as usize
+    Some(id as usize)
+}
+
+fn default_error_sink(err: ClientError) {
+    print_err!("language server client error: {}", err);
 }
 
 #[allow(dead_code)]
-impl<W: Write> LanguageServerRef<W> {
-    fn new(peer: W) -> Self {
-        LanguageServerRef(Arc::new(Mutex::new(LanguageServer {
+impl<W: 'static + Write + Send> LanguageServerRef<W> {
+    pub(crate) fn new(peer: W, process: Option<Child>) -> Self {
+        let lang_server = LanguageServerRef(Arc::new(Mutex::new(LanguageServer {
             peer,
             pending: HashMap::new(),
             next_id: 1,
-        })))
+            cancelled: BTreeSet::new(),
+            default_timeout: None,
+            disconnected: false,
+            process,
+            exit_status: None,
+            error_sink: Arc::new(default_error_sink),
+            request_handlers: HashMap::new(),
+            request_hooks: Vec::new(),
+            notification_hooks: Vec::new(),
+            subscribers: Vec::new(),
+            next_subscriber_id: 1,
+            partial_results: HashMap::new(),
+            next_partial_result_token: 1,
+            capabilities: ServerCapabilityState::default(),
+            check_capabilities: false,
+        })));
+
+        let weak = lang_server.downgrade();
+        lang_server.on_typed_request::<RegisterCapability, _>(move |params| {
+            if let Some(lang_server) = weak.upgrade() {
+                let mut inner = lang_server.0.lock().unwrap();
+                inner.capabilities.register(params.registrations);
+            }
+            Ok(())
+        });
+        let weak = lang_server.downgrade();
+        lang_server.on_typed_request::<UnregisterCapability, _>(move |params| {
+            if let Some(lang_server) = weak.upgrade() {
+                let ids: Vec<_> = params.unregisterations.into_iter().map(|u| u.id).collect();
+                let mut inner = lang_server.0.lock().unwrap();
+                inner.capabilities.unregister(&ids);
+            }
+            Ok(())
+        });
+        lang_server
     }
 
     /// Writes `msg` to the underlying process's stdin. Exposed for testing & debugging;
     /// you should not need to call this method directly.
-    fn write(&self, msg: &str) {
+    fn write(&self, msg: &str) -> Result<(), ClientError> {
+        let mut inner = self.0.lock().unwrap();
+        inner.write(msg)
+    }
+
+    /// Sets the function that receives errors which cannot be returned to a caller, such as
+    /// malformed or unexpected messages from the server. By default these are printed to
+    /// stderr.
+    pub fn set_error_sink<F>(&self, sink: F)
+    where
+        F: 'static + Send + Sync + Fn(ClientError),
+    {
+        let mut inner = self.0.lock().unwrap();
+        inner.error_sink = Arc::new(sink);
+    }
+
+    /// Adds a function to be called before each request is sent, outside the server's lock,
+    /// so that it can send messages that must precede the request.
+    pub(crate) fn add_request_hook<F>(&self, hook: F)
+    where
+        F: 'static + Send + Sync + Fn(&str, &Value),
+    {
+        let mut inner = self.0.lock().unwrap();
+        inner.request_hooks.push(Arc::new(hook));
+    }
+
+    /// Like [`add_request_hook`](Self::add_request_hook), but called before each
+    /// notification.
+    pub(crate) fn add_notification_hook<F>(&self, hook: F)
+    where
+        F: 'static + Send + Sync + Fn(&str, &Value),
+    {
+        let mut inner = self.0.lock().unwrap();
+        inner.notification_hooks.push(Arc::new(hook));
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakLanguageServerRef<W> {
+        WeakLanguageServerRef(Arc::downgrade(&self.0))
+    }
+
+    /// Passes `err` to the error sink.
+    pub(crate) fn report_error(&self, err: ClientError) {
+        let sink = self.0.lock().unwrap().error_sink.clone();
+        sink(err);
+    }
+
+    /// Registers `handler` to answer requests for `method` sent by the server, replacing any
+    /// existing handler for that method. The handler is given the request's params, and its
+    /// result or error is sent back as the response.
+    ///
+    /// Requests for methods without a handler are answered with a `MethodNotFound` error.
+    /// The handler runs on the reader thread; see [`LanguageServerRef`].
+    pub fn on_request<F>(&self, method: &str, handler: F)
+    where
+        F: 'static + Send + Sync + Fn(Value) -> Result<Value, ResponseError>,
+    {
+        let mut inner = self.0.lock().unwrap();
+        inner
+            .request_handlers
+            .insert(method.to_owned(), Arc::new(handler));
+    }
+
+    /// Removes the handler for requests for `method`, if there is one.
+    pub fn remove_request_handler(&self, method: &str) {
+        self.0.lock().unwrap().request_handlers.remove(method);
+    }
+
+    /// Whether a handler is registered for requests for `method`.
+    pub fn handles_request(&self, method: &str) -> bool {
+        self.0.lock().unwrap().request_handlers.contains_key(method)
+    }
+
+    /// Dispatches a request sent by the server to its handler, and sends the response.
+    fn handle_request(&self, val: &Value) -> Result<(), ClientError> {
+        let id = &val["id"];
+        let method = val["method"].as_str().unwrap_or_default();
+        let handler = self.0.lock().unwrap().request_handlers.get(method).cloned();
+        let result = match handler {
+            Some(handler) => handler(val.get("params").cloned().unwrap_or(Value::Null)),
+            None => Err(ResponseError::method_not_found(method)),
+        };
+        self.0.lock().unwrap().send_response(id, result)
+    }
+
+    fn add_subscriber(&self, method: Option<&str>, handler: NotificationHandler) -> Subscription {
         let mut inner = self.0.lock().unwrap();
-        inner.write(msg);
+        let id = inner.next_subscriber_id;
+        inner.next_subscriber_id += 1;
+        inner.subscribers.push(Subscriber {
+            id,
+            method: method.map(str::to_owned),
+            handler,
+        });
+        Subscription::new(id, Arc::downgrade(&self.0) as Weak<dyn Unsubscribe>)
+    }
+
+    /// Calls `handler` with every notification for `method` sent by the server, until the
+    /// returned [`Subscription`] is dropped. The handler runs on the reader thread; see
+    /// [`LanguageServerRef`].
+    pub fn subscribe<F>(&self, method: &str, handler: F) -> Subscription
+    where
+        F: 'static + Send + Sync + Fn(Notification),
+    {
+        self.add_subscriber(Some(method), Arc::new(handler))
+    }
+
+    /// Like [`subscribe`](Self::subscribe), but receives every notification regardless of
+    /// its method.
+    pub fn subscribe_all<F>(&self, handler: F) -> Subscription
+    where
+        F: 'static + Send + Sync + Fn(Notification),
+    {
+        self.add_subscriber(None, Arc::new(handler))
+    }
+
+    /// Delivers every notification for `method` to the returned channel, until the returned
+    /// [`Subscription`] is dropped. The channel closes if the server disconnects.
+    pub fn subscribe_channel(&self, method: &str) -> (mpsc::Receiver<Notification>, Subscription) {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let subscription = self.subscribe(method, move |notification| {
+            let _ = tx.lock().unwrap().send(notification);
+        });
+        (rx, subscription)
+    }
+
+    /// Like [`subscribe_channel`](Self::subscribe_channel), but receives every notification
+    /// regardless of its method.
+    pub fn subscribe_all_channel(&self) -> (mpsc::Receiver<Notification>, Subscription) {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let subscription = self.subscribe_all(move |notification| {
+            let _ = tx.lock().unwrap().send(notification);
+        });
+        (rx, subscription)
+    }
+
+    /// Passes a notification sent by the server to each interested subscriber.
+    fn handle_notification(&self, val: &Value) {
+        let method = val["method"].as_str().unwrap_or_default();
+        if method == "$/progress" {
+            if let Some(handler) = self.partial_result_handler(&val["params"]["token"]) {
+                handler(val["params"]["value"].clone());
+                return;
+            }
+        }
+        let handlers: Vec<_> = {
+            let inner = self.0.lock().unwrap();
+            inner
+                .subscribers
+                .iter()
+                .filter(|sub| sub.method.as_deref().is_none_or(|m| m == method))
+                .map(|sub| sub.handler.clone())
+                .collect()
+        };
+        let notification = Notification {
+            method: method.to_owned(),
+            params: val.get("params").cloned().unwrap_or(Value::Null),
+        };
+        for handler in handlers {
+            handler(notification.clone());
+        }
+    }
+
+    /// The handler for the partial results sent with `token`, if it belongs to a pending
+    /// request.
+    fn partial_result_handler(&self, token: &Value) -> Option<PartialResultHandler> {
+        let token = serde_json::from_value::<ProgressToken>(token.clone()).ok()?;
+        let inner = self.0.lock().unwrap();
+        inner.partial_results.get(&token).cloned()
     }
 
     //TODO: real logging (with slog?)
-    fn handle_msg(&self, val: &Value) {
+    pub(crate) fn handle_msg(&self, val: &Value) -> Result<(), ClientError> {
         // TODO avoid what looks like a round trip
         let parsed = val.to_string();
-        let parsed = JsonRPC::parse(&parsed).expect("problem creating JsonRpc instance");
+        let parsed = JsonRPC::parse(&parsed)
+            .map_err(|err| ClientError::Protocol(format!("invalid JSON-RPC message: {}", err)))?;
         match parsed {
-            JsonRPC::Request(obj) => print_err!("client received unexpected request: {:?}", obj),
-            JsonRPC::Notification(obj) => println!("recv notification: {:?}", obj),
+            JsonRPC::Request(_) => self.handle_request(val)?,
+            JsonRPC::Notification(_) => self.handle_notification(val),
             JsonRPC::Success(_) => {
-                let mut inner = self.0.lock().unwrap();
-                let id = number_from_id(val.get("id"));
-                let _ = val.get("result").expect("response missing 'result' field");
-                // TODO clone
-                inner.handle_response(id, val.clone());
+                let id = val.get("id").unwrap_or(&Value::Null);
+                let callback = self.0.lock().unwrap().take_callback(id)?;
+                if let Some(callback) = callback {
+                    // TODO clone
+                    callback.call(Ok(val.clone()));
+                }
             }
             JsonRPC::Error(_) => {
-                // TODO I'm not sure why this was this way before.
-                // if val.get("id").expect("error missing id field").is_null() {
-                //     let mut inner = self.0.lock().unwrap();
-                //     // TODO clone
-                //     inner.handle_error(number_from_id(val.get("id")), val.clone());
-                // } else {
-                //     print_err!("received error: {:?}", obj);
-                // }
+                let id = val.get("id").unwrap_or(&Value::Null);
+                if id.is_null() {
+                    // the server could not determine which request failed
+                    return Err(ClientError::Protocol(format!(
+                        "received error without request id: {}",
+                        val
+                    )));
+                }
+                let callback = self.0.lock().unwrap().take_callback(id)?;
+                if let Some(callback) = callback {
+                    // TODO clone
+                    callback.call(Err(val.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Called by the reader thread once the server's output has closed. Every request still
+    /// awaiting a response is completed with a server disconnected error.
+    pub(crate) fn handle_disconnect(&self) {
+        let pending = {
+            let mut inner = self.0.lock().unwrap();
+            inner.disconnect()
+        };
+        let error = ResponseError::server_disconnected();
+        for (id, callback) in pending {
+            callback.call(Err(error.to_response(&json!(id))));
+        }
+    }
+
+    /// Returns `false` once the server has closed its output.
+    pub fn is_connected(&self) -> bool {
+        !self.0.lock().unwrap().disconnected
+    }
+
+    /// Returns the server process's exit status, if it was spawned by this client and has
+    /// exited. This does not block.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.0.lock().unwrap().poll_exit_status()
+    }
+
+    /// Blocks until the server process exits, or until `timeout` elapses.
+    ///
+    /// Returns `None` if the timeout elapsed first, or if there is no process to wait for.
+    pub fn wait_for_exit(&self, timeout: Option<Duration>) -> Option<ExitStatus> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            {
                 let mut inner = self.0.lock().unwrap();
-                inner.handle_error(number_from_id(val.get("id")), val.clone());
+                inner.process.as_ref()?;
+                if let Some(status) = inner.poll_exit_status() {
+                    return Some(status);
+                }
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return None;
             }
+            thread::sleep(EXIT_POLL_INTERVAL);
         }
     }
 
+    /// Forcibly terminates the server process, if this client spawned it.
+    pub fn kill(&self) -> std::io::Result<()> {
+        let mut inner = self.0.lock().unwrap();
+        match inner.process {
+            Some(ref mut child) => child.kill(),
+            None => Ok(()),
+        }
+    }
+
+    /// The capabilities the server returned from `initialize`, updated by any
+    /// `client/registerCapability` and `client/unregisterCapability` requests since.
+    ///
+    /// Returns `None` until the server has responded to `initialize`.
+    pub fn capabilities(&self) -> Option<ServerCapabilities> {
+        self.0.lock().unwrap().capabilities.capabilities().cloned()
+    }
+
+    /// The server's current dynamic registrations.
+    pub fn registrations(&self) -> Vec<Registration> {
+        self.0.lock().unwrap().capabilities.registrations().to_vec()
+    }
+
+    /// Whether the server has advertised support for requests for `method`, either in its
+    /// `initialize` result or through dynamic registration.
+    ///
+    /// Methods that servers do not advertise, such as `shutdown`, are always supported.
+    /// Others are unsupported until the server has responded to `initialize`.
+    pub fn supports(&self, method: &str) -> bool {
+        self.0.lock().unwrap().capabilities.supports(method)
+    }
+
+    /// The position encoding the server chose during `initialize`, which is UTF-16 unless
+    /// the server says otherwise.
+    pub fn position_encoding(&self) -> PositionEncoding {
+        let inner = self.0.lock().unwrap();
+        inner
+            .capabilities
+            .capabilities()
+            .and_then(|capabilities| capabilities.position_encoding.as_ref())
+            .and_then(PositionEncoding::from_kind)
+            .unwrap_or_default()
+    }
+
+    /// When enabled, requests for methods that the server has not advertised support for
+    /// fail with [`ClientError::Unsupported`] instead of being sent. Disabled by default.
+    pub fn set_capability_checks(&self, enabled: bool) {
+        self.0.lock().unwrap().check_capabilities = enabled;
+    }
+
+    /// Sets the timeout applied to requests sent with [`send_request`](Self::send_request).
+    /// By default, requests never time out.
+    pub fn set_default_timeout(&self, timeout: Option<Duration>) {
+        self.0.lock().unwrap().default_timeout = timeout;
+    }
+
     /// Sends a JSON-RPC request message with the provided method and parameters.
     /// `completion` should be a callback which will be executed with the server's response.
-    pub fn send_request<CB>(&self, method: &str, params: &Value, completion: CB)
+    ///
+    /// If the server disconnects before responding, `completion` receives an error response
+    /// whose code is [`codes::SERVER_DISCONNECTED`](crate::error::codes::SERVER_DISCONNECTED).
+    /// If the request cannot be sent at all, `completion` is dropped without being called.
+    ///
+    /// The returned handle can be used to cancel the request. The request is subject to the
+    /// default timeout; see [`send_request_with_timeout`](Self::send_request_with_timeout).
+    pub fn send_request<CB>(
+        &self,
+        method: &str,
+        params: &Value,
+        completion: CB,
+    ) -> Result<RequestHandle, ClientError>
     where
         CB: 'static + Send + FnOnce(Result<Value, Value>),
     {
-        let mut inner = self.0.lock().unwrap();
-        inner.send_request(method, params, Box::new(completion));
+        let timeout = self.0.lock().unwrap().default_timeout;
+        self.send_request_with_timeout(method, params, timeout, completion)
+    }
+
+    /// Sends a JSON-RPC request which is abandoned if the server has not responded within
+    /// `timeout`. In that case `$/cancelRequest` is sent to the server, `completion` receives
+    /// an error response whose code is
+    /// [`codes::REQUEST_TIMED_OUT`](crate::error::codes::REQUEST_TIMED_OUT), and the
+    /// server's response is discarded if it arrives later.
+    pub fn send_request_with_timeout<CB>(
+        &self,
+        method: &str,
+        params: &Value,
+        timeout: Option<Duration>,
+        completion: CB,
+    ) -> Result<RequestHandle, ClientError>
+    where
+        CB: 'static + Send + FnOnce(Result<Value, Value>),
+    {
+        let mut completion: Callback = Box::new(completion);
+        if method == "initialize" {
+            // keep hold of the capabilities before anyone else sees the result
+            let weak = self.downgrade();
+            completion = Box::new(move |result: Result<Value, Value>| {
+                if let (Ok(response), Some(lang_server)) = (&result, weak.upgrade()) {
+                    let capabilities = response["result"]["capabilities"].clone();
+                    let mut inner = lang_server.0.lock().unwrap();
+                    inner.capabilities.set_initial(capabilities);
+                }
+                completion.call(result)
+            });
+        }
+        let hooks = self.0.lock().unwrap().request_hooks.clone();
+        for hook in hooks {
+            hook(method, params);
+        }
+        let id = {
+            let mut inner = self.0.lock().unwrap();
+            inner.send_request(method, params, completion)?
+        };
+        let server: Weak<dyn Abandon> = Arc::downgrade(&self.0) as Weak<dyn Abandon>;
+        if let Some(timeout) = timeout {
+            let server = server.clone();
+            timer::schedule(timeout, move || {
+                if let Some(server) = server.upgrade() {
+                    server.abandon(id, ResponseError::request_timed_out());
+                }
+            });
+        }
+        Ok(RequestHandle { id, server })
+    }
+
+    /// Sends a JSON-RPC request with a `partialResultToken` added to its params, which must
+    /// be an object. Each partial result the server streams back is passed to
+    /// `partial_result` before the response reaches `completion`.
+    ///
+    /// Partial results are handled ahead of any subscribers, which never see them, and stop
+    /// as soon as the request completes, fails or is cancelled. Like
+    /// [`send_request`](Self::send_request), this is subject to the default timeout.
+    pub fn send_request_with_partial_results<P, CB>(
+        &self,
+        method: &str,
+        params: &Value,
+        partial_result: P,
+        completion: CB,
+    ) -> Result<RequestHandle, ClientError>
+    where
+        P: 'static + Send + Sync + Fn(Value),
+        CB: 'static + Send + FnOnce(Result<Value, Value>),
+    {
+        let timeout = self.0.lock().unwrap().default_timeout;
+        self.send_request_with_partial_results_and_timeout(
+            method,
+            params,
+            timeout,
+            partial_result,
+            completion,
+        )
+    }
+
+    /// Like [`send_request_with_partial_results`](Self::send_request_with_partial_results),
+    /// but subject to `timeout` rather than the default timeout; see
+    /// [`send_request_with_timeout`](Self::send_request_with_timeout).
+    pub(crate) fn send_request_with_partial_results_and_timeout<P, CB>(
+        &self,
+        method: &str,
+        params: &Value,
+        timeout: Option<Duration>,
+        partial_result: P,
+        completion: CB,
+    ) -> Result<RequestHandle, ClientError>
+    where
+        P: 'static + Send + Sync + Fn(Value),
+        CB: 'static + Send + FnOnce(Result<Value, Value>),
+    {
+        if !params.is_object() {
+            return Err(ClientError::Protocol(format!(
+                "params for {} must be an object to carry a partialResultToken",
+                method
+            )));
+        }
+        let token = {
+            let mut inner = self.0.lock().unwrap();
+            let token = ProgressToken::String(format!(
+                "lsp-client/partial-result/{}",
+                inner.next_partial_result_token
+            ));
+            inner.next_partial_result_token += 1;
+            inner
+                .partial_results
+                .insert(token.clone(), Arc::new(partial_result));
+            token
+        };
+        let mut params = params.clone();
+        params["partialResultToken"] = serde_json::to_value(&token)?;
+
+        let weak = self.downgrade();
+        let finished = token.clone();
+        let sent = self.send_request_with_timeout(method, &params, timeout, move |result| {
+            if let Some(lang_server) = weak.upgrade() {
+                let mut inner = lang_server.0.lock().unwrap();
+                inner.partial_results.remove(&finished);
+            }
+            completion(result)
+        });
+        if sent.is_err() {
+            self.0.lock().unwrap().partial_results.remove(&token);
+        }
+        sent
     }
 
     /// Sends a JSON-RPC notification message with the provided method and parameters.
-    pub fn send_notification(&self, method: &str, params: &Value) {
+    pub fn send_notification(&self, method: &str, params: &Value) -> Result<(), ClientError> {
+        let hooks = self.0.lock().unwrap().notification_hooks.clone();
+        for hook in hooks {
+            hook(method, params);
+        }
         let mut inner = self.0.lock().unwrap();
-        inner.send_notification(method, params);
+        inner.send_notification(method, params)
+    }
+}
+
+/// A reference to a LanguageServer that does not keep it alive, for use by handlers that
+/// are themselves owned by the server.
+pub(crate) struct WeakLanguageServerRef<W: Write>(Weak<Mutex<LanguageServer<W>>>);
+
+impl<W: Write> WeakLanguageServerRef<W> {
+    pub(crate) fn upgrade(&self) -> Option<LanguageServerRef<W>> {
+        self.0.upgrade().map(LanguageServerRef)
+    }
+}
+
+impl<W: Write> Clone for WeakLanguageServerRef<W> {
+    fn clone(&self) -> Self {
+        WeakLanguageServerRef(self.0.clone())
     }
 }
 
@@ -207,35 +913,98 @@ impl<W: Write> Clone for LanguageServerRef<W> {
     }
 }
 
-pub fn start_language_server(mut child: Child) -> (Child, LanguageServerRef<ChildStdin>) {
-    let child_stdin = child.stdin.take().unwrap();
-    let child_stdout = child.stdout.take().unwrap();
-    let lang_server = LanguageServerRef::new(child_stdin);
-    {
-        let lang_server = lang_server.clone();
-        thread::spawn(move || {
-            let mut reader = BufReader::new(child_stdout);
-            loop {
-                match parsing::read_message(&mut reader) {
-                    Ok(ref val) => lang_server.handle_msg(val),
-                    Err(ParseError::Empty) => (),
-                    Err(err) => eprintln!("parse error: {:?}", err),
-                };
-            }
-        });
-    }
-    (child, lang_server)
+/// Takes ownership of a spawned language server, communicating with it over its stdin and
+/// stdout. Fails with `InvalidInput` unless both are piped, after killing the server.
+///
+/// A reader thread dispatches the server's messages until its stdout closes, at which point
+/// any outstanding requests fail and the thread exits. See [`transport::connect`] for
+/// servers that are reached some other way.
+pub fn start_language_server(child: Child) -> io::Result<LanguageServerRef<ChildStdin>> {
+    transport::connect(child)
+}
+
+/// Starts a thread that dispatches the messages read from `reader` until it closes, at which
+/// point any outstanding requests fail and the thread exits.
+pub(crate) fn spawn_reader<R, W>(lang_server: &LanguageServerRef<W>, reader: R)
+where
+    R: 'static + Read + Send,
+    W: 'static + Write + Send,
+{
+    let lang_server = lang_server.clone();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        loop {
+            match parsing::read_message(&mut reader) {
+                Ok(ref val) => {
+                    if let Err(err) = lang_server.handle_msg(val) {
+                        lang_server.report_error(err);
+                    }
+                }
+                // end of stream: the server has closed its output, or exited
+                Err(ParseError::Empty) => break,
+                Err(ParseError::Io(err)) => {
+                    lang_server.report_error(ClientError::Io(err));
+                    break;
+                }
+                Err(err) => lang_server.report_error(ClientError::Parse(err)),
+            };
+        }
+        lang_server.handle_disconnect();
+    });
 }
 
 #[cfg(test)]
-mod tests {
-    use std::{
-        process::{Child, Command, Stdio},
-        sync::mpsc,
-    };
+pub(crate) mod tests {
+    use std::process::{Child, Command, Stdio};
 
     use super::*;
 
+    /// A writer whose contents can be inspected after it is handed to a LanguageServerRef.
+    #[derive(Clone, Default)]
+    pub(crate) struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuffer {
+        pub(crate) fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+
+        /// The messages written so far, without their headers.
+        pub(crate) fn messages(&self) -> Vec<Value> {
+            self.contents()
+                .split("Content-Length: ")
+                .filter(|s| !s.is_empty())
+                .map(|s| serde_json::from_str(s.split_once("\r\n\r\n").unwrap().1).unwrap())
+                .collect()
+        }
+    }
+
+    /// Sends `initialize` and answers it with `capabilities`, as the server would.
+    pub(crate) fn initialize<W: 'static + Write + Send>(
+        lang_server: &LanguageServerRef<W>,
+        capabilities: Value,
+    ) {
+        let id = lang_server
+            .send_request("initialize", &json!({}), |_| ())
+            .unwrap()
+            .id();
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": {"capabilities": capabilities},
+        });
+        lang_server.handle_msg(&response).unwrap();
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
     fn prepare_command() -> Child {
         Command::new("rust-analyzer")
             .stdin(Stdio::piped())
@@ -246,7 +1015,7 @@ mod tests {
 
     #[test]
     fn test_start_language_server() {
-        let (mut child, lang_server) = start_language_server(prepare_command());
+        let lang_server = start_language_server(prepare_command()).unwrap();
 
         let (tx, rx) = mpsc::channel();
         let init = json!({
@@ -254,9 +1023,11 @@ mod tests {
             "initialization_options": {},
             "capabilities": {},
         });
-        lang_server.send_request("initialize", &init, move |result| {
-            let _ = tx.send(result);
-        });
+        lang_server
+            .send_request("initialize", &init, move |result| {
+                let _ = tx.send(result);
+            })
+            .unwrap();
         let initialize_result = rx.recv().expect("problem receiving from channel");
         println!("received response {initialize_result:#?}");
         assert!(initialize_result.is_ok());
@@ -266,13 +1037,17 @@ mod tests {
         assert!(error.is_none());
 
         let initialized = json!({});
-        lang_server.send_notification("initialized", &initialized);
+        lang_server
+            .send_notification("initialized", &initialized)
+            .unwrap();
 
         let (tx, rx) = mpsc::channel();
         let shutdown = json!(());
-        lang_server.send_request("shutdown", &shutdown, move |result| {
-            let _ = tx.send(result);
-        });
+        lang_server
+            .send_request("shutdown", &shutdown, move |result| {
+                let _ = tx.send(result);
+            })
+            .unwrap();
         let shutdown_result = rx.recv().expect("problem receiving from channel");
         println!("received response {shutdown_result:#?}");
         assert!(shutdown_result.is_ok());
@@ -282,14 +1057,14 @@ mod tests {
         assert!(error.is_none());
 
         let exit = json!({});
-        lang_server.send_notification("exit", &exit);
+        lang_server.send_notification("exit", &exit).unwrap();
 
-        let _ = child.wait();
+        let _ = lang_server.wait_for_exit(None);
     }
 
     #[test]
     fn test_language_server_bad_arguments() {
-        let (mut child, lang_server) = start_language_server(prepare_command());
+        let lang_server = start_language_server(prepare_command()).unwrap();
 
         let (tx, rx) = mpsc::channel();
         let init = json!({
@@ -297,9 +1072,11 @@ mod tests {
             "initialization_options": {},
             "capabilities": {},
         });
-        lang_server.send_request("initialize", &init, move |result| {
-            let _ = tx.send(result);
-        });
+        lang_server
+            .send_request("initialize", &init, move |result| {
+                let _ = tx.send(result);
+            })
+            .unwrap();
         let initialize_result = rx.recv().expect("problem receiving from channel");
         println!("received response {initialize_result:#?}");
         assert!(initialize_result.is_ok());
@@ -309,14 +1086,18 @@ mod tests {
         assert!(error.is_none());
 
         let initialized = json!({});
-        lang_server.send_notification("initialized", &initialized);
+        lang_server
+            .send_notification("initialized", &initialized)
+            .unwrap();
 
         let (tx, rx) = mpsc::channel();
         // should be null, not a map, even an empty one
         let shutdown = json!({});
-        lang_server.send_request("shutdown", &shutdown, move |result| {
-            let _ = tx.send(result);
-        });
+        lang_server
+            .send_request("shutdown", &shutdown, move |result| {
+                let _ = tx.send(result);
+            })
+            .unwrap();
         let shutdown_result = rx.recv().expect("problem receiving from channel");
         println!("received response {shutdown_result:#?}");
         assert!(shutdown_result.is_err());
@@ -327,8 +1108,362 @@ mod tests {
 
         // we can still exist normally
         let exit = json!({});
-        lang_server.send_notification("exit", &exit);
+        lang_server.send_notification("exit", &exit).unwrap();
+
+        let _ = lang_server.wait_for_exit(None);
+    }
+
+    #[test]
+    fn test_start_language_server_unpiped() {
+        let child = Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .expect("failed to start sleep");
+        let pid = child.id().to_string();
+        let err = start_language_server(child).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // the server has been killed and reaped
+        let alive = Command::new("kill").args(["-0", &pid]).status().unwrap();
+        assert!(!alive.success());
+    }
+
+    #[test]
+    fn test_server_exit_fails_pending_requests() {
+        // reads the start of the request, then exits without responding
+        let child = Command::new("sh")
+            .args(["-c", "head -c 1 > /dev/null; exit 3"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to start sh");
+        let lang_server = start_language_server(child).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        lang_server
+            .send_request("initialize", &json!({}), move |result| {
+                let _ = tx.send(result);
+            })
+            .unwrap();
+        let result = rx.recv().expect("callback was never called");
+        let error = ResponseError::from_response(&result.unwrap_err()).unwrap();
+        assert_eq!(error, ResponseError::server_disconnected());
+
+        let status = lang_server.wait_for_exit(Some(Duration::from_secs(5)));
+        assert_eq!(status.and_then(|s| s.code()), Some(3));
+        assert_eq!(lang_server.exit_status().and_then(|s| s.code()), Some(3));
+
+        // once disconnected, new messages fail immediately
+        assert!(!lang_server.is_connected());
+        let sent = lang_server.send_request("shutdown", &json!(null), |_| ());
+        assert!(matches!(sent, Err(ClientError::Disconnected)));
+        let sent = lang_server.send_notification("exit", &json!(null));
+        assert!(matches!(sent, Err(ClientError::Disconnected)));
+    }
+
+    #[test]
+    fn test_unexpected_responses_are_errors() {
+        let lang_server = LanguageServerRef::new(Vec::new(), None);
+        let (tx, rx) = mpsc::channel();
+        lang_server
+            .send_request("shutdown", &json!(null), move |result| {
+                let _ = tx.send(result);
+            })
+            .unwrap();
+
+        let unknown = json!({"jsonrpc": "2.0", "id": 42, "result": null});
+        let result = lang_server.handle_msg(&unknown);
+        assert!(matches!(result, Err(ClientError::UnknownResponseId(id)) if id == json!(42)));
+
+        let bad_id = json!({"jsonrpc": "2.0", "id": [1], "result": null});
+        assert!(lang_server.handle_msg(&bad_id).is_err());
+
+        let no_id = json!({
+            "jsonrpc": "2.0",
+            "id": null,
+            "error": {"code": -32700, "message": "parse error"},
+        });
+        assert!(matches!(
+            lang_server.handle_msg(&no_id),
+            Err(ClientError::Protocol(_))
+        ));
+
+        // string ids are accepted for our numeric requests
+        let response = json!({"jsonrpc": "2.0", "id": "1", "result": null});
+        lang_server.handle_msg(&response).unwrap();
+        assert!(rx.recv().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_request_timeout_sends_cancel() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        lang_server.set_default_timeout(Some(Duration::from_secs(60)));
+
+        let (tx, rx) = mpsc::channel();
+        lang_server
+            .send_request_with_timeout(
+                "textDocument/hover",
+                &json!({}),
+                Some(Duration::from_millis(10)),
+                move |result| {
+                    let _ = tx.send(result);
+                },
+            )
+            .unwrap();
+        let result = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let error = ResponseError::from_response(&result.unwrap_err()).unwrap();
+        assert_eq!(error, ResponseError::request_timed_out());
+        assert!(buffer
+            .contents()
+            .contains(r#"{"jsonrpc":"2.0","method":"$/cancelRequest","params":{"id":1}}"#));
+
+        // the late response is dropped, rather than reported as unknown
+        let late = json!({"jsonrpc": "2.0", "id": 1, "result": null});
+        lang_server.handle_msg(&late).unwrap();
+        assert!(lang_server.handle_msg(&late).is_err());
+    }
+
+    #[test]
+    fn test_cancel_request() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+
+        let (tx, rx) = mpsc::channel();
+        let handle = lang_server
+            .send_request("textDocument/completion", &json!({}), move |result| {
+                let _ = tx.send(result);
+            })
+            .unwrap();
+        assert_eq!(handle.id(), 1);
+        handle.cancel();
+        let error = ResponseError::from_response(&rx.recv().unwrap().unwrap_err()).unwrap();
+        assert_eq!(error, ResponseError::request_cancelled());
+        assert!(buffer
+            .contents()
+            .contains(r#"{"jsonrpc":"2.0","method":"$/cancelRequest","params":{"id":1}}"#));
+
+        // cancelling again, or after the server has answered, has no effect
+        let before = buffer.contents();
+        handle.cancel();
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "error": {"code": -32800, "message": "cancelled"},
+        });
+        lang_server.handle_msg(&response).unwrap();
+        handle.cancel();
+        assert_eq!(buffer.contents(), before);
+
+        // requests the server never answers are not remembered forever
+        for _ in 0..MAX_CANCELLED + 10 {
+            lang_server
+                .send_request("textDocument/completion", &json!({}), |_| ())
+                .unwrap()
+                .cancel();
+        }
+        let cancelled = lang_server.0.lock().unwrap().cancelled.clone();
+        assert_eq!(cancelled.len(), MAX_CANCELLED);
+        assert_eq!(cancelled.first(), Some(&12));
+    }
 
-        let _ = child.wait();
+    #[test]
+    fn test_server_requests_are_answered() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        lang_server.on_request("workspace/configuration", |params| {
+            let items = params["items"].as_array().map(Vec::len).unwrap_or(0);
+            Ok(Value::Array(vec![Value::Null; items]))
+        });
+        lang_server.on_request("client/registerCapability", |_| {
+            Err(ResponseError::new(-32803, "not supported"))
+        });
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": "config-1",
+            "method": "workspace/configuration",
+            "params": {"items": [{"section": "a"}, {"section": "b"}]},
+        });
+        lang_server.handle_msg(&request).unwrap();
+        assert!(buffer
+            .contents()
+            .ends_with(r#"{"id":"config-1","jsonrpc":"2.0","result":[null,null]}"#));
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": "client/registerCapability",
+            "params": {"registrations": []},
+        });
+        lang_server.handle_msg(&request).unwrap();
+        assert!(buffer.contents().ends_with(
+            r#"{"error":{"code":-32803,"message":"not supported"},"id":7,"jsonrpc":"2.0"}"#
+        ));
+
+        lang_server.remove_request_handler("client/registerCapability");
+        lang_server.handle_msg(&request).unwrap();
+        assert!(buffer.contents().contains(r#""code":-32601"#));
+    }
+
+    #[test]
+    fn test_notification_subscriptions() {
+        let lang_server = LanguageServerRef::new(Vec::new(), None);
+        let (diagnostics, diagnostics_sub) =
+            lang_server.subscribe_channel("textDocument/publishDiagnostics");
+        let (all, all_sub) = lang_server.subscribe_all_channel();
+        let (tx, logs) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let log_sub = lang_server.subscribe("window/logMessage", move |notification| {
+            let _ = tx
+                .lock()
+                .unwrap()
+                .send(notification.params["message"].clone());
+        });
+
+        let log = json!({
+            "jsonrpc": "2.0",
+            "method": "window/logMessage",
+            "params": {"type": 3, "message": "hello"},
+        });
+        let publish = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": "file:///a.rs", "diagnostics": []},
+        });
+        lang_server.handle_msg(&log).unwrap();
+        lang_server.handle_msg(&publish).unwrap();
+
+        assert_eq!(logs.try_recv().unwrap(), json!("hello"));
+        assert!(logs.try_recv().is_err());
+        let received = diagnostics.try_recv().unwrap();
+        assert_eq!(received.params["uri"], json!("file:///a.rs"));
+        assert!(diagnostics.try_recv().is_err());
+        let methods: Vec<_> = all.try_iter().map(|n| n.method).collect();
+        assert_eq!(
+            methods,
+            ["window/logMessage", "textDocument/publishDiagnostics"]
+        );
+
+        // dropping a guard removes its handler, and closes its channel
+        drop(log_sub);
+        drop(diagnostics_sub);
+        lang_server.handle_msg(&log).unwrap();
+        lang_server.handle_msg(&publish).unwrap();
+        assert!(matches!(
+            logs.try_recv(),
+            Err(mpsc::TryRecvError::Disconnected)
+        ));
+        assert!(matches!(
+            diagnostics.try_recv(),
+            Err(mpsc::TryRecvError::Disconnected)
+        ));
+        assert_eq!(all.try_iter().count(), 2);
+        drop(all_sub);
+    }
+
+    #[test]
+    fn test_partial_results() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        let (progress, _progress_sub) = lang_server.subscribe_channel("$/progress");
+        let (tx, rx) = mpsc::channel();
+        let partial = Mutex::new(tx.clone());
+        lang_server
+            .send_request_with_partial_results(
+                "workspace/symbol",
+                &json!({"query": "foo"}),
+                move |value| partial.lock().unwrap().send(value).unwrap(),
+                move |result| tx.send(result.unwrap()["result"].clone()).unwrap(),
+            )
+            .unwrap();
+        let request = buffer.messages().pop().unwrap();
+        assert_eq!(request["params"]["query"], "foo");
+        let token = request["params"]["partialResultToken"].clone();
+        assert!(token.is_string());
+
+        let notification = |token: &Value, value: Value| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "$/progress",
+                "params": {"token": token, "value": value},
+            })
+        };
+        lang_server
+            .handle_msg(&notification(&token, json!([1, 2])))
+            .unwrap();
+        lang_server
+            .handle_msg(&notification(&json!("other"), json!({"kind": "end"})))
+            .unwrap();
+        lang_server
+            .handle_msg(&notification(&token, json!([3])))
+            .unwrap();
+        lang_server
+            .handle_msg(&json!({"jsonrpc": "2.0", "id": request["id"], "result": []}))
+            .unwrap();
+        let received: Vec<_> = rx.try_iter().collect();
+        assert_eq!(received, [json!([1, 2]), json!([3]), json!([])]);
+
+        // only the unrelated progress reaches subscribers, as does anything after completion
+        lang_server
+            .handle_msg(&notification(&token, json!([4])))
+            .unwrap();
+        let tokens: Vec<_> = progress
+            .try_iter()
+            .map(|n| n.params["token"].clone())
+            .collect();
+        assert_eq!(tokens, [json!("other"), token]);
+
+        assert!(lang_server
+            .send_request_with_partial_results("shutdown", &json!(null), |_| (), |_| ())
+            .is_err());
+    }
+
+    #[test]
+    fn test_capabilities_are_tracked() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        lang_server.set_capability_checks(true);
+        assert!(lang_server.capabilities().is_none());
+        assert!(!lang_server.supports("textDocument/hover"));
+
+        lang_server
+            .send_request("initialize", &json!({}), |_| ())
+            .unwrap();
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": {"capabilities": {"hoverProvider": true}},
+        });
+        lang_server.handle_msg(&response).unwrap();
+        assert!(lang_server.capabilities().unwrap().hover_provider.is_some());
+        assert!(lang_server.supports("textDocument/hover"));
+
+        let rename = lang_server.send_request("textDocument/rename", &json!({}), |_| ());
+        assert!(matches!(rename, Err(ClientError::Unsupported(m)) if m == "textDocument/rename"));
+
+        let register = json!({
+            "jsonrpc": "2.0",
+            "id": "reg",
+            "method": "client/registerCapability",
+            "params": {"registrations": [{"id": "r1", "method": "textDocument/rename"}]},
+        });
+        lang_server.handle_msg(&register).unwrap();
+        assert!(buffer
+            .contents()
+            .ends_with(r#"{"id":"reg","jsonrpc":"2.0","result":null}"#));
+        assert!(lang_server.supports("textDocument/rename"));
+        assert_eq!(lang_server.registrations().len(), 1);
+        lang_server
+            .send_request("textDocument/rename", &json!({}), |_| ())
+            .unwrap();
+
+        let unregister = json!({
+            "jsonrpc": "2.0",
+            "id": "unreg",
+            "method": "client/unregisterCapability",
+            "params": {"unregisterations": [{"id": "r1", "method": "textDocument/rename"}]},
+        });
+        lang_server.handle_msg(&unregister).unwrap();
+        assert!(!lang_server.supports("textDocument/rename"));
     }
 }
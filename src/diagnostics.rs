@@ -0,0 +1,981 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! Collects the diagnostics reported by the server.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::sync::{Arc, Mutex, Weak};
+
+use lsp_types::notification::{
+    DidCloseTextDocument, DidOpenTextDocument, Notification as LspNotification, PublishDiagnostics,
+};
+use lsp_types::request::{
+    DocumentDiagnosticRequest, Request, WorkspaceDiagnosticRefresh, WorkspaceDiagnosticRequest,
+};
+use lsp_types::{
+    Diagnostic, DiagnosticServerCapabilities, DiagnosticSeverity, DocumentDiagnosticParams,
+    DocumentDiagnosticReport, DocumentDiagnosticReportKind, DocumentDiagnosticReportResult,
+    NumberOrString, PartialResultParams, PreviousResultId, Range, TextDocumentIdentifier, Url,
+    WorkDoneProgressParams, WorkspaceDiagnosticParams, WorkspaceDiagnosticReportPartialResult,
+    WorkspaceDiagnosticReportResult, WorkspaceDocumentDiagnosticReport,
+};
+use serde_json::value::Value;
+
+use crate::client::{LanguageServerRef, RequestHandle, Subscription, Unsubscribe};
+use crate::documents::DocumentStore;
+use crate::error::ClientError;
+use crate::typed::{typed_result, TypedError};
+
+/// The diagnostics for a document have changed.
+#[derive(Debug, Clone)]
+pub struct DiagnosticsChange {
+    pub uri: Url,
+    /// The version of the document the diagnostics apply to, if the server said.
+    pub version: Option<i32>,
+    /// The new diagnostics, which are empty if they have all been cleared.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Selects diagnostics by their properties. An empty filter matches every diagnostic.
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticFilter {
+    severity: Option<DiagnosticSeverity>,
+    source: Option<String>,
+    code: Option<NumberOrString>,
+    range: Option<Range>,
+}
+
+impl DiagnosticFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Matches diagnostics at least as severe as `severity`. Diagnostics without a severity
+    /// are treated as errors.
+    pub fn severity(mut self, severity: DiagnosticSeverity) -> Self {
+        self.severity = Some(severity);
+        self
+    }
+
+    /// Matches diagnostics from `source`, such as `rustc`.
+    pub fn source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_owned());
+        self
+    }
+
+    pub fn code(mut self, code: NumberOrString) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// Matches diagnostics whose range overlaps or touches `range`.
+    pub fn range(mut self, range: Range) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    pub fn matches(&self, diagnostic: &Diagnostic) -> bool {
+        if let Some(severity) = self.severity {
+            if diagnostic.severity.unwrap_or(DiagnosticSeverity::ERROR) > severity {
+                return false;
+            }
+        }
+        if self.source.is_some() && diagnostic.source != self.source {
+            return false;
+        }
+        if self.code.is_some() && diagnostic.code != self.code {
+            return false;
+        }
+        if let Some(range) = self.range {
+            if diagnostic.range.end < range.start || range.end < diagnostic.range.start {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+type Listener = Arc<dyn Fn(&DiagnosticsChange) + Send + Sync>;
+
+type VersionLookup = Arc<dyn Fn(&Url) -> Option<i32> + Send + Sync>;
+
+struct Entry {
+    diagnostics: Vec<Diagnostic>,
+    /// Identifies pulled diagnostics, for the server to report them unchanged.
+    result_id: Option<String>,
+}
+
+struct Diagnostics {
+    entries: HashMap<Url, Entry>,
+    /// The latest version of each open document that diagnostics were published for, which
+    /// those published later must not be older than. Forgotten when the document is opened
+    /// or closed, as its versions then start again.
+    versions: HashMap<Url, i32>,
+    listeners: Vec<(usize, Listener)>,
+    next_listener_id: usize,
+}
+
+struct Shared {
+    diagnostics: Mutex<Diagnostics>,
+    /// The current version of an open document, to recognize diagnostics for older ones.
+    document_version: Option<VersionLookup>,
+}
+
+impl Shared {
+    fn document_version(&self, uri: &Url) -> Option<i32> {
+        self.document_version
+            .as_ref()
+            .and_then(|lookup| lookup(uri))
+    }
+
+    /// Replaces the diagnostics for `uri`, unless they are for an older version of the
+    /// document than those already published while it has been open, or than the open
+    /// document itself. Returns whether they were accepted.
+    fn update(
+        &self,
+        uri: Url,
+        version: Option<i32>,
+        diagnostics: Vec<Diagnostic>,
+        result_id: Option<String>,
+    ) -> bool {
+        let current = self.document_version(&uri);
+        if let (Some(version), Some(current)) = (version, current) {
+            if version < current {
+                return false;
+            }
+        }
+        let listeners = {
+            let mut inner = self.diagnostics.lock().unwrap();
+            if let Some(version) = version {
+                let latest = inner.versions.entry(uri.clone()).or_insert(version);
+                if version < *latest {
+                    return false;
+                }
+                *latest = version;
+            }
+            if diagnostics.is_empty() && result_id.is_none() {
+                inner.entries.remove(&uri);
+            } else {
+                let entry = Entry {
+                    diagnostics: diagnostics.clone(),
+                    result_id,
+                };
+                inner.entries.insert(uri.clone(), entry);
+            }
+            inner
+                .listeners
+                .iter()
+                .map(|(_, listener)| listener.clone())
+                .collect::<Vec<_>>()
+        };
+        let change = DiagnosticsChange {
+            uri,
+            version,
+            diagnostics,
+        };
+        for listener in listeners {
+            listener(&change);
+        }
+        true
+    }
+
+    /// Records that the diagnostics for `uri` are still those identified by `result_id`.
+    fn unchanged(&self, uri: Url, result_id: String) {
+        let mut inner = self.diagnostics.lock().unwrap();
+        let entry = inner.entries.entry(uri).or_insert_with(|| Entry {
+            diagnostics: Vec::new(),
+            result_id: None,
+        });
+        entry.result_id = Some(result_id);
+    }
+
+    fn result_id(&self, uri: &Url) -> Option<String> {
+        let inner = self.diagnostics.lock().unwrap();
+        inner
+            .entries
+            .get(uri)
+            .and_then(|entry| entry.result_id.clone())
+    }
+
+    fn result_ids(&self) -> Vec<PreviousResultId> {
+        let inner = self.diagnostics.lock().unwrap();
+        inner
+            .entries
+            .iter()
+            .filter_map(|(uri, entry)| {
+                let value = entry.result_id.clone()?;
+                Some(PreviousResultId {
+                    uri: uri.clone(),
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    fn apply_related(&self, related: Option<HashMap<Url, DocumentDiagnosticReportKind>>) {
+        for (uri, report) in related.unwrap_or_default() {
+            match report {
+                DocumentDiagnosticReportKind::Full(report) => {
+                    self.update(uri, None, report.items, report.result_id);
+                }
+                DocumentDiagnosticReportKind::Unchanged(report) => {
+                    self.unchanged(uri, report.result_id)
+                }
+            }
+        }
+    }
+
+    /// Records a document diagnostic report for `uri`, which was at `version` when it was
+    /// requested.
+    fn apply_document_report(
+        &self,
+        uri: &Url,
+        version: Option<i32>,
+        result: DocumentDiagnosticReportResult,
+    ) {
+        match result {
+            DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(report)) => {
+                let full = report.full_document_diagnostic_report;
+                self.update(uri.clone(), version, full.items, full.result_id);
+                self.apply_related(report.related_documents);
+            }
+            DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Unchanged(report)) => {
+                let result_id = report.unchanged_document_diagnostic_report.result_id;
+                self.unchanged(uri.clone(), result_id);
+                self.apply_related(report.related_documents);
+            }
+            DocumentDiagnosticReportResult::Partial(partial) => {
+                self.apply_related(partial.related_documents)
+            }
+        }
+    }
+
+    fn apply_workspace_reports(&self, reports: Vec<WorkspaceDocumentDiagnosticReport>) {
+        for report in reports {
+            match report {
+                WorkspaceDocumentDiagnosticReport::Full(report) => {
+                    let version = report.version.map(|version| version as i32);
+                    let full = report.full_document_diagnostic_report;
+                    self.update(report.uri, version, full.items, full.result_id);
+                }
+                WorkspaceDocumentDiagnosticReport::Unchanged(report) => {
+                    let result_id = report.unchanged_document_diagnostic_report.result_id;
+                    self.unchanged(report.uri, result_id);
+                }
+            }
+        }
+    }
+}
+
+impl Unsubscribe for Shared {
+    fn unsubscribe(&self, id: usize) {
+        let removed = {
+            let mut inner = self.diagnostics.lock().unwrap();
+            let index = inner.listeners.iter().position(|(i, _)| *i == id);
+            index.map(|index| inner.listeners.remove(index))
+        };
+        drop(removed);
+    }
+}
+
+/// The latest diagnostics for each document, as published by the server with
+/// `textDocument/publishDiagnostics`, or pulled with a [`DiagnosticPuller`].
+///
+/// Diagnostics that the server publishes for an older version of a document than it has
+/// already published for since the document was opened are dropped. When the store is
+/// created [`with_documents`](Self::with_documents), so are diagnostics for an older version
+/// than the one open.
+#[derive(Clone)]
+pub struct DiagnosticStore {
+    shared: Arc<Shared>,
+    _subscription: Arc<Subscription>,
+}
+
+impl DiagnosticStore {
+    pub fn new<W: 'static + Write + Send>(server: &LanguageServerRef<W>) -> Self {
+        Self::with_version_lookup(server, None)
+    }
+
+    /// Creates a store that drops diagnostics for older versions of the documents open in
+    /// `documents`.
+    pub fn with_documents<W: 'static + Write + Send>(documents: &DocumentStore<W>) -> Self {
+        let server = documents.server().clone();
+        let documents = documents.clone();
+        Self::with_version_lookup(&server, Some(Arc::new(move |uri| documents.version(uri))))
+    }
+
+    fn with_version_lookup<W: 'static + Write + Send>(
+        server: &LanguageServerRef<W>,
+        document_version: Option<VersionLookup>,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            diagnostics: Mutex::new(Diagnostics {
+                entries: HashMap::new(),
+                versions: HashMap::new(),
+                listeners: Vec::new(),
+                next_listener_id: 1,
+            }),
+            document_version,
+        });
+        let weak = Arc::downgrade(&shared);
+        let subscription = server.subscribe_typed::<PublishDiagnostics, _>(move |params| {
+            if let Some(shared) = weak.upgrade() {
+                shared.update(params.uri, params.version, params.diagnostics, None);
+            }
+        });
+        let weak = Arc::downgrade(&shared);
+        server.add_notification_hook(move |method, params| {
+            if method != DidOpenTextDocument::METHOD && method != DidCloseTextDocument::METHOD {
+                return;
+            }
+            let uri = params["textDocument"]["uri"]
+                .as_str()
+                .and_then(|uri| Url::parse(uri).ok());
+            if let (Some(shared), Some(uri)) = (weak.upgrade(), uri) {
+                shared.diagnostics.lock().unwrap().versions.remove(&uri);
+            }
+        });
+        DiagnosticStore {
+            shared,
+            _subscription: Arc::new(subscription),
+        }
+    }
+
+    /// The diagnostics for `uri`.
+    pub fn get(&self, uri: &Url) -> Vec<Diagnostic> {
+        let inner = self.shared.diagnostics.lock().unwrap();
+        inner
+            .entries
+            .get(uri)
+            .map(|entry| entry.diagnostics.clone())
+            .unwrap_or_default()
+    }
+
+    /// The documents that have any diagnostics.
+    pub fn uris(&self) -> Vec<Url> {
+        let inner = self.shared.diagnostics.lock().unwrap();
+        inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| !entry.diagnostics.is_empty())
+            .map(|(uri, _)| uri.clone())
+            .collect()
+    }
+
+    /// The diagnostics for `uri` that match `filter`.
+    pub fn query_uri(&self, uri: &Url, filter: &DiagnosticFilter) -> Vec<Diagnostic> {
+        let inner = self.shared.diagnostics.lock().unwrap();
+        match inner.entries.get(uri) {
+            Some(entry) => entry
+                .diagnostics
+                .iter()
+                .filter(|diagnostic| filter.matches(diagnostic))
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The diagnostics for every document that match `filter`.
+    pub fn query(&self, filter: &DiagnosticFilter) -> Vec<(Url, Diagnostic)> {
+        let inner = self.shared.diagnostics.lock().unwrap();
+        inner
+            .entries
+            .iter()
+            .flat_map(|(uri, entry)| {
+                entry
+                    .diagnostics
+                    .iter()
+                    .filter(|diagnostic| filter.matches(diagnostic))
+                    .map(move |diagnostic| (uri.clone(), diagnostic.clone()))
+            })
+            .collect()
+    }
+
+    /// Calls `handler` whenever the diagnostics for a document change, for as long as the
+    /// returned [`Subscription`] is kept.
+    pub fn on_change<F>(&self, handler: F) -> Subscription
+    where
+        F: 'static + Send + Sync + Fn(&DiagnosticsChange),
+    {
+        let mut inner = self.shared.diagnostics.lock().unwrap();
+        let id = inner.next_listener_id;
+        inner.next_listener_id += 1;
+        inner.listeners.push((id, Arc::new(handler)));
+        Subscription::new(id, Arc::downgrade(&self.shared) as Weak<dyn Unsubscribe>)
+    }
+}
+
+/// Documents and workspaces whose diagnostics have been pulled, to pull again on refresh.
+#[derive(Default)]
+struct Pulled {
+    documents: HashSet<Url>,
+    workspace: bool,
+    /// The workspace pull still waiting for its response, which a new one supersedes.
+    workspace_request: Option<RequestHandle>,
+}
+
+/// Pulls diagnostics from servers that support `textDocument/diagnostic` and
+/// `workspace/diagnostic`, recording them in a [`DiagnosticStore`] alongside any that are
+/// pushed.
+///
+/// The result ids of earlier reports are sent with each pull, so that the server can report
+/// diagnostics unchanged instead of sending them again, and partial results are recorded as
+/// they arrive. When the server sends `workspace/diagnostic/refresh`, every document pulled
+/// so far is pulled again, as is the workspace if it has been pulled. Documents are no
+/// longer pulled once they are [forgotten](Self::forget), or closed if the store was created
+/// [`with_documents`](DiagnosticStore::with_documents).
+///
+/// Servers only offer pull diagnostics to clients that advertise them; see
+/// [`ClientCapabilitiesBuilder::pull_diagnostics`](crate::capabilities::ClientCapabilitiesBuilder::pull_diagnostics).
+pub struct DiagnosticPuller<W: Write> {
+    server: LanguageServerRef<W>,
+    shared: Arc<Shared>,
+    pulled: Arc<Mutex<Pulled>>,
+}
+
+impl<W: Write> Clone for DiagnosticPuller<W> {
+    fn clone(&self) -> Self {
+        DiagnosticPuller {
+            server: self.server.clone(),
+            shared: self.shared.clone(),
+            pulled: self.pulled.clone(),
+        }
+    }
+}
+
+impl<W: 'static + Write + Send> DiagnosticPuller<W> {
+    pub fn new(server: LanguageServerRef<W>, store: &DiagnosticStore) -> Self {
+        let puller = DiagnosticPuller {
+            server,
+            shared: store.shared.clone(),
+            pulled: Arc::new(Mutex::new(Pulled::default())),
+        };
+        let lang_server = puller.server.downgrade();
+        let shared = Arc::downgrade(&puller.shared);
+        let pulled = Arc::downgrade(&puller.pulled);
+        puller
+            .server
+            .on_typed_request::<WorkspaceDiagnosticRefresh, _>(move |()| {
+                if let (Some(server), Some(shared), Some(pulled)) =
+                    (lang_server.upgrade(), shared.upgrade(), pulled.upgrade())
+                {
+                    DiagnosticPuller {
+                        server,
+                        shared,
+                        pulled,
+                    }
+                    .refresh();
+                }
+                Ok(())
+            });
+        puller
+    }
+
+    /// The identifier the server asked to be sent with each pull, if any.
+    fn identifier(&self) -> Option<String> {
+        match self.server.capabilities()?.diagnostic_provider? {
+            DiagnosticServerCapabilities::Options(options) => options.identifier,
+            DiagnosticServerCapabilities::RegistrationOptions(options) => {
+                options.diagnostic_options.identifier
+            }
+        }
+    }
+
+    /// Wraps `handler`, which fails if a value is not a valid partial result, into a handler
+    /// for [`request_with_partial_results`](LanguageServerRef::request_with_partial_results).
+    fn partial_result_handler<F>(&self, handler: F) -> impl Fn(Value) + Send + Sync
+    where
+        F: 'static + Send + Sync + Fn(Value) -> Result<(), serde_json::Error>,
+    {
+        let lang_server = self.server.downgrade();
+        move |value| {
+            if let Err(err) = handler(value) {
+                if let Some(lang_server) = lang_server.upgrade() {
+                    lang_server.report_error(ClientError::Protocol(format!(
+                        "invalid partial result: {}",
+                        err
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Pulls the diagnostics for `uri`, then calls `completion`.
+    pub fn pull_document<CB>(&self, uri: &Url, completion: CB) -> Result<RequestHandle, ClientError>
+    where
+        CB: 'static + Send + FnOnce(Result<(), TypedError>),
+    {
+        let version = self.shared.document_version(uri);
+        let params = DocumentDiagnosticParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            identifier: self.identifier(),
+            previous_result_id: self.shared.result_id(uri),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+
+        let shared = Arc::downgrade(&self.shared);
+        let document = uri.clone();
+        let partial_results = self.partial_result_handler(move |value| {
+            let result = serde_json::from_value(value)?;
+            if let Some(shared) = shared.upgrade() {
+                shared.apply_document_report(&document, version, result);
+            }
+            Ok(())
+        });
+        self.pulled.lock().unwrap().documents.insert(uri.clone());
+
+        let shared = Arc::downgrade(&self.shared);
+        let uri = uri.clone();
+        self.server
+            .request_with_partial_results::<DocumentDiagnosticRequest, _, _>(
+                params,
+                partial_results,
+                move |result| {
+                    let result = result.map(|report| {
+                        if let Some(shared) = shared.upgrade() {
+                            shared.apply_document_report(&uri, version, report);
+                        }
+                    });
+                    completion(result)
+                },
+            )
+    }
+
+    /// Pulls the diagnostics for the whole workspace, then calls `completion`.
+    ///
+    /// Servers may keep this request open for as long as they like, streaming reports as
+    /// partial results, so it is sent without a timeout. A workspace pull still in flight is
+    /// cancelled first.
+    pub fn pull_workspace<CB>(&self, completion: CB) -> Result<RequestHandle, ClientError>
+    where
+        CB: 'static + Send + FnOnce(Result<(), TypedError>),
+    {
+        let params = WorkspaceDiagnosticParams {
+            identifier: self.identifier(),
+            previous_result_ids: self.shared.result_ids(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+
+        let shared = Arc::downgrade(&self.shared);
+        let partial_results = self.partial_result_handler(move |value| {
+            let result: WorkspaceDiagnosticReportPartialResult = serde_json::from_value(value)?;
+            if let Some(shared) = shared.upgrade() {
+                shared.apply_workspace_reports(result.items);
+            }
+            Ok(())
+        });
+        let previous = {
+            let mut pulled = self.pulled.lock().unwrap();
+            pulled.workspace = true;
+            pulled.workspace_request.take()
+        };
+        if let Some(previous) = previous {
+            previous.cancel();
+        }
+
+        let params = serde_json::to_value(params)?;
+        let shared = Arc::downgrade(&self.shared);
+        let handle = self.server.send_request_with_partial_results_and_timeout(
+            WorkspaceDiagnosticRequest::METHOD,
+            &params,
+            None,
+            partial_results,
+            move |result| {
+                let result = typed_result::<WorkspaceDiagnosticRequest>(result).map(|report| {
+                    let items = match report {
+                        WorkspaceDiagnosticReportResult::Report(report) => report.items,
+                        WorkspaceDiagnosticReportResult::Partial(partial) => partial.items,
+                    };
+                    if let Some(shared) = shared.upgrade() {
+                        shared.apply_workspace_reports(items);
+                    }
+                });
+                completion(result)
+            },
+        )?;
+        self.pulled.lock().unwrap().workspace_request = Some(handle.clone());
+        Ok(handle)
+    }
+
+    /// Stops pulling the diagnostics for `uri` on refresh, as once it has been closed.
+    pub fn forget(&self, uri: &Url) {
+        self.pulled.lock().unwrap().documents.remove(uri);
+    }
+
+    /// Pulls again everything that has been pulled so far.
+    pub fn refresh(&self) {
+        let (documents, workspace) = {
+            let mut pulled = self.pulled.lock().unwrap();
+            if self.shared.document_version.is_some() {
+                let shared = &self.shared;
+                pulled
+                    .documents
+                    .retain(|uri| shared.document_version(uri).is_some());
+            }
+            (pulled.documents.clone(), pulled.workspace)
+        };
+        let mut sent = Vec::new();
+        for uri in documents {
+            sent.push(self.pull_document(&uri, |_| ()));
+        }
+        if workspace {
+            sent.push(self.pull_workspace(|_| ()));
+        }
+        for err in sent.into_iter().filter_map(Result::err) {
+            self.server.report_error(err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use lsp_types::Position;
+
+    use super::*;
+    use crate::client::tests::{initialize, SharedBuffer};
+    use crate::error::codes;
+
+    fn publish(
+        uri: &str,
+        version: Option<i32>,
+        diagnostics: serde_json::Value,
+    ) -> serde_json::Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "version": version, "diagnostics": diagnostics},
+        })
+    }
+
+    fn diagnostic(line: u32, severity: i32, source: &str, code: &str) -> serde_json::Value {
+        json!({
+            "range": {"start": {"line": line, "character": 0}, "end": {"line": line, "character": 4}},
+            "severity": severity,
+            "source": source,
+            "code": code,
+            "message": "something is wrong",
+        })
+    }
+
+    #[test]
+    fn test_queries() {
+        let lang_server = LanguageServerRef::new(Vec::new(), None);
+        let store = DiagnosticStore::new(&lang_server);
+        let a = Url::parse("file:///a.rs").unwrap();
+        let msg = publish(
+            a.as_str(),
+            None,
+            json!([
+                diagnostic(0, 1, "rustc", "E0308"),
+                diagnostic(3, 2, "clippy", "needless_return"),
+                diagnostic(7, 4, "rustc", "unused"),
+            ]),
+        );
+        lang_server.handle_msg(&msg).unwrap();
+        let msg = publish(
+            "file:///b.rs",
+            None,
+            json!([diagnostic(1, 1, "rustc", "E0425")]),
+        );
+        lang_server.handle_msg(&msg).unwrap();
+
+        assert_eq!(store.get(&a).len(), 3);
+        let errors = DiagnosticFilter::new().severity(DiagnosticSeverity::ERROR);
+        assert_eq!(store.query(&errors).len(), 2);
+        let warnings = DiagnosticFilter::new().severity(DiagnosticSeverity::WARNING);
+        assert_eq!(store.query_uri(&a, &warnings).len(), 2);
+        let rustc = DiagnosticFilter::new().source("rustc");
+        assert_eq!(store.query_uri(&a, &rustc).len(), 2);
+        let code = DiagnosticFilter::new().code(NumberOrString::String("unused".into()));
+        assert_eq!(store.query(&code)[0].1.range.start.line, 7);
+        let range = Range::new(Position::new(2, 0), Position::new(7, 0));
+        let in_range = store.query_uri(&a, &DiagnosticFilter::new().range(range));
+        assert_eq!(in_range.len(), 2);
+
+        lang_server
+            .handle_msg(&publish(a.as_str(), None, json!([])))
+            .unwrap();
+        assert!(store.get(&a).is_empty());
+        assert_eq!(store.uris().len(), 1);
+    }
+
+    #[test]
+    fn test_stale_diagnostics_are_dropped() {
+        let lang_server = LanguageServerRef::new(Vec::new(), None);
+        let documents = DocumentStore::new(lang_server.clone());
+        let store = DiagnosticStore::with_documents(&documents);
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let subscription = store.on_change(move |change| {
+            let _ = tx
+                .lock()
+                .unwrap()
+                .send((change.version, change.diagnostics.len()));
+        });
+
+        let uri = Url::parse("file:///a.rs").unwrap();
+        documents.open(uri.clone(), "rust", String::new()).unwrap();
+        documents.set_text(&uri, "x".into()).unwrap();
+        let one = json!([diagnostic(0, 1, "rustc", "E0425")]);
+        let two = json!([
+            diagnostic(0, 1, "rustc", "E0425"),
+            diagnostic(0, 2, "rustc", "W")
+        ]);
+
+        // older than the open document
+        lang_server
+            .handle_msg(&publish(uri.as_str(), Some(1), one.clone()))
+            .unwrap();
+        assert!(store.get(&uri).is_empty());
+        lang_server
+            .handle_msg(&publish(uri.as_str(), Some(2), two.clone()))
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), (Some(2), 2));
+
+        documents.set_text(&uri, "y".into()).unwrap();
+        lang_server
+            .handle_msg(&publish(uri.as_str(), Some(3), one.clone()))
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), (Some(3), 1));
+        // older again, now that the document has moved on
+        lang_server
+            .handle_msg(&publish(uri.as_str(), Some(2), json!([])))
+            .unwrap();
+        assert_eq!(store.get(&uri).len(), 1);
+
+        // a reopened document starts again at version 1, even though the server did not
+        // clear its diagnostics on close
+        documents.close(&uri).unwrap();
+        documents.open(uri.clone(), "rust", String::new()).unwrap();
+        lang_server
+            .handle_msg(&publish(uri.as_str(), Some(1), two.clone()))
+            .unwrap();
+        assert_eq!(rx.recv().unwrap(), (Some(1), 2));
+        assert_eq!(store.get(&uri).len(), 2);
+
+        drop(subscription);
+        lang_server
+            .handle_msg(&publish(uri.as_str(), None, json!([])))
+            .unwrap();
+        assert!(store.get(&uri).is_empty());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_stale_diagnostics_without_documents() {
+        let lang_server = LanguageServerRef::new(Vec::new(), None);
+        let store = DiagnosticStore::new(&lang_server);
+        let uri = Url::parse("file:///a.rs").unwrap();
+        let one = json!([diagnostic(0, 1, "rustc", "E0425")]);
+        let publish_version = |version, diagnostics| {
+            let msg = publish(uri.as_str(), Some(version), diagnostics);
+            lang_server.handle_msg(&msg).unwrap();
+        };
+
+        publish_version(3, one.clone());
+        // older than those already published, even once they have been cleared
+        publish_version(4, json!([]));
+        publish_version(2, one.clone());
+        assert!(store.get(&uri).is_empty());
+
+        // until the document is reopened, when its versions start again
+        let item = json!({"uri": uri, "languageId": "rust", "version": 1, "text": ""});
+        lang_server
+            .send_notification("textDocument/didOpen", &json!({ "textDocument": item }))
+            .unwrap();
+        publish_version(1, one);
+        assert_eq!(store.get(&uri).len(), 1);
+    }
+
+    #[test]
+    fn test_pull_diagnostics() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        let provider = json!({
+            "identifier": "rust",
+            "interFileDependencies": true,
+            "workspaceDiagnostics": true,
+        });
+        initialize(&lang_server, json!({"diagnosticProvider": provider}));
+        let store = DiagnosticStore::new(&lang_server);
+        let puller = DiagnosticPuller::new(lang_server.clone(), &store);
+        let a = Url::parse("file:///a.rs").unwrap();
+        let b = Url::parse("file:///b.rs").unwrap();
+        let last_request = || buffer.messages().pop().unwrap();
+        let respond = |id: &serde_json::Value, result: serde_json::Value| {
+            let response = json!({"jsonrpc": "2.0", "id": id, "result": result});
+            lang_server.handle_msg(&response).unwrap();
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let done = tx.clone();
+        puller
+            .pull_document(&a, move |result| done.send(result.is_ok()).unwrap())
+            .unwrap();
+        let request = last_request();
+        assert_eq!(request["method"], "textDocument/diagnostic");
+        assert_eq!(request["params"]["identifier"], "rust");
+        assert!(request["params"]["previousResultId"].is_null());
+
+        // related documents stream in before the response
+        let token = &request["params"]["partialResultToken"];
+        let related = json!({"file:///b.rs": {
+            "kind": "full",
+            "resultId": "b1",
+            "items": [diagnostic(0, 1, "rustc", "E0425")],
+        }});
+        let progress = json!({
+            "jsonrpc": "2.0",
+            "method": "$/progress",
+            "params": {"token": token, "value": {"relatedDocuments": related}},
+        });
+        lang_server.handle_msg(&progress).unwrap();
+        assert_eq!(store.get(&b).len(), 1);
+        let items = json!([
+            diagnostic(0, 1, "rustc", "E0425"),
+            diagnostic(1, 2, "rustc", "W")
+        ]);
+        respond(
+            &request["id"],
+            json!({"kind": "full", "resultId": "a1", "items": items}),
+        );
+        assert!(rx.recv().unwrap());
+        assert_eq!(store.get(&a).len(), 2);
+
+        let done = tx.clone();
+        puller
+            .pull_document(&a, move |result| done.send(result.is_ok()).unwrap())
+            .unwrap();
+        let request = last_request();
+        assert_eq!(request["params"]["previousResultId"], "a1");
+        respond(
+            &request["id"],
+            json!({"kind": "unchanged", "resultId": "a2"}),
+        );
+        assert!(rx.recv().unwrap());
+        assert_eq!(store.get(&a).len(), 2);
+
+        puller
+            .pull_workspace(move |result| tx.send(result.is_ok()).unwrap())
+            .unwrap();
+        let request = last_request();
+        let mut previous = request["params"]["previousResultIds"]
+            .as_array()
+            .unwrap()
+            .clone();
+        previous.sort_by_key(|id| id["value"].as_str().unwrap().to_owned());
+        assert_eq!(
+            previous,
+            [
+                json!({"uri": "file:///a.rs", "value": "a2"}),
+                json!({"uri": "file:///b.rs", "value": "b1"}),
+            ]
+        );
+        let items = json!([{"kind": "full", "uri": "file:///b.rs", "version": null, "items": []}]);
+        respond(&request["id"], json!({"items": items}));
+        assert!(rx.recv().unwrap());
+        assert!(store.get(&b).is_empty());
+        assert_eq!(store.uris(), [a]);
+
+        let count = buffer.messages().len();
+        let refresh =
+            json!({"jsonrpc": "2.0", "id": "r", "method": "workspace/diagnostic/refresh"});
+        lang_server.handle_msg(&refresh).unwrap();
+        let methods: Vec<_> = buffer.messages()[count..]
+            .iter()
+            .map(|m| m["method"].as_str().map(str::to_owned))
+            .collect();
+        assert_eq!(
+            methods,
+            [
+                Some("textDocument/diagnostic".to_owned()),
+                Some("workspace/diagnostic".to_owned()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_refresh_prunes_and_supersedes() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        lang_server.set_default_timeout(Some(Duration::from_millis(10)));
+        let provider = json!({"interFileDependencies": false, "workspaceDiagnostics": true});
+        initialize(&lang_server, json!({"diagnosticProvider": provider}));
+        let documents = DocumentStore::new(lang_server.clone());
+        let store = DiagnosticStore::with_documents(&documents);
+        let puller = DiagnosticPuller::new(lang_server.clone(), &store);
+        let a = Url::parse("file:///a.rs").unwrap();
+        let b = Url::parse("file:///b.rs").unwrap();
+        documents.open(a.clone(), "rust", String::new()).unwrap();
+        documents.open(b.clone(), "rust", String::new()).unwrap();
+        puller.pull_document(&a, |_| ()).unwrap();
+        puller.pull_document(&b, |_| ()).unwrap();
+        documents.close(&b).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        puller
+            .pull_workspace(move |result| tx.send(result).unwrap())
+            .unwrap();
+        let first = buffer.messages().pop().unwrap();
+        // workspace pulls are not subject to the default timeout
+        thread::sleep(Duration::from_millis(50));
+        assert!(rx.try_recv().is_err());
+
+        let count = buffer.messages().len();
+        let refresh =
+            json!({"jsonrpc": "2.0", "id": "r", "method": "workspace/diagnostic/refresh"});
+        lang_server.handle_msg(&refresh).unwrap();
+        match rx.recv().unwrap() {
+            Err(TypedError::Response(err)) => assert_eq!(err.code, codes::REQUEST_CANCELLED),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let messages = &buffer.messages()[count..];
+        let summary: Vec<_> = messages
+            .iter()
+            .map(|m| {
+                (
+                    m["method"].clone(),
+                    m["params"]["textDocument"]["uri"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (json!("textDocument/diagnostic"), json!(a.as_str())),
+                (json!("$/cancelRequest"), json!(null)),
+                (json!("workspace/diagnostic"), json!(null)),
+                (json!(null), json!(null)),
+            ]
+        );
+        assert_eq!(messages[1]["params"]["id"], first["id"]);
+    }
+}
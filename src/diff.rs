@@ -0,0 +1,274 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! Computing the incremental changes that turn one version of a text into another.
+
+use std::ops::Range;
+
+use lsp_types::{Range as LspRange, TextDocumentContentChangeEvent};
+
+use crate::position::{LineIndex, PositionEncoding};
+
+/// Beyond this many differing lines, the differing region is replaced as a whole.
+const MAX_LINE_EDITS: usize = 1000;
+
+/// A range of old lines, and the range of new lines that replaces it.
+type Hunk = (Range<usize>, Range<usize>);
+
+/// Returns the changes that turn `old` into `new`, with ranges in `encoding`.
+///
+/// Lines are compared first, and each differing region is then narrowed down to the
+/// characters that actually differ. The changes are ordered from the end of the text to the
+/// start, so that each range still refers to `old` when the changes are applied in order.
+pub fn diff(
+    old: &str,
+    new: &str,
+    encoding: PositionEncoding,
+) -> Vec<TextDocumentContentChangeEvent> {
+    if old == new {
+        return Vec::new();
+    }
+    let old_lines = lines(old);
+    let new_lines = lines(new);
+    let hunks = line_hunks(old, new, &old_lines, &new_lines)
+        .unwrap_or_else(|| vec![(0..old_lines.len(), 0..new_lines.len())]);
+
+    let index = LineIndex::new(old);
+    let span = |lines: &[Range<usize>], range: Range<usize>, fallback: usize| match lines
+        .get(range.start)
+    {
+        Some(first) if !range.is_empty() => first.start..lines[range.end - 1].end,
+        Some(first) => first.start..first.start,
+        None => fallback..fallback,
+    };
+    let mut changes: Vec<_> = hunks
+        .into_iter()
+        .filter_map(|(old_hunk, new_hunk)| {
+            let old_hunk = span(&old_lines, old_hunk, old.len());
+            let new_hunk = span(&new_lines, new_hunk, new.len());
+            let (old_hunk, new_hunk) = trim(old, new, old_hunk, new_hunk)?;
+            Some(TextDocumentContentChangeEvent {
+                range: Some(LspRange::new(
+                    index.position(old_hunk.start, encoding),
+                    index.position(old_hunk.end, encoding),
+                )),
+                range_length: None,
+                text: new[new_hunk].to_owned(),
+            })
+        })
+        .collect();
+    changes.reverse();
+    changes
+}
+
+/// The byte ranges of the lines of `text`, each including its `\n`.
+fn lines(text: &str) -> Vec<Range<usize>> {
+    let mut start = 0;
+    text.split_inclusive('\n')
+        .map(|line| {
+            start += line.len();
+            start - line.len()..start
+        })
+        .collect()
+}
+
+/// Whether `offset` falls between the `\r` and `\n` of a line ending.
+fn splits_crlf(text: &str, offset: usize) -> bool {
+    text[..offset].ends_with('\r') && text[offset..].starts_with('\n')
+}
+
+/// Narrows a pair of ranges down to the part that differs, or `None` if nothing does. The
+/// ranges never start or end inside a `\r\n` of `old`, which a position cannot express.
+fn trim(
+    old: &str,
+    new: &str,
+    mut old_range: Range<usize>,
+    mut new_range: Range<usize>,
+) -> Option<(Range<usize>, Range<usize>)> {
+    let (a, b) = (&old[old_range.clone()], &new[new_range.clone()]);
+    let mut prefix = a
+        .char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i);
+    if prefix > 0 && splits_crlf(old, old_range.start + prefix) {
+        prefix -= 1;
+    }
+    old_range.start += prefix;
+    new_range.start += prefix;
+
+    let (a, b) = (&old[old_range.clone()], &new[new_range.clone()]);
+    let mut suffix: usize = a
+        .chars()
+        .rev()
+        .zip(b.chars().rev())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum();
+    if suffix > 0 && splits_crlf(old, old_range.end - suffix) {
+        suffix -= 1;
+    }
+    old_range.end -= suffix;
+    new_range.end -= suffix;
+
+    if old_range.is_empty() && new_range.is_empty() {
+        None
+    } else {
+        Some((old_range, new_range))
+    }
+}
+
+/// Compares lines with Myers' algorithm, returning the pairs of line ranges that differ, or
+/// `None` if there are too many differences to be worth finding.
+fn line_hunks(
+    old: &str,
+    new: &str,
+    old_lines: &[Range<usize>],
+    new_lines: &[Range<usize>],
+) -> Option<Vec<Hunk>> {
+    let (n, m) = (old_lines.len() as isize, new_lines.len() as isize);
+    let equal = |x: isize, y: isize| {
+        old[old_lines[x as usize].clone()] == new[new_lines[y as usize].clone()]
+    };
+    let max = n + m;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    // the furthest x reached on each diagonal, before each round
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max {
+        if d as usize > MAX_LINE_EDITS {
+            return None;
+        }
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && equal(x, y) {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // walk back through the rounds, collecting the lines that match
+    let mut matches = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            matches.push((x as usize, y as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    matches.reverse();
+
+    let mut hunks = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    for (match_x, match_y) in matches.into_iter().chain(Some((n as usize, m as usize))) {
+        if match_x > x || match_y > y {
+            hunks.push((x..match_x, y..match_y));
+        }
+        x = match_x + 1;
+        y = match_y + 1;
+    }
+    Some(hunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(text: &str, changes: &[TextDocumentContentChangeEvent]) -> String {
+        let mut text = text.to_owned();
+        for change in changes {
+            let range = change.range.unwrap();
+            let index = LineIndex::new(&text);
+            let start = index.offset(range.start, PositionEncoding::Utf16);
+            let end = index.offset(range.end, PositionEncoding::Utf16);
+            text.replace_range(start..end, &change.text);
+        }
+        text
+    }
+
+    #[test]
+    fn test_diff_round_trips() {
+        let cases = [
+            ("", ""),
+            ("", "abc\n"),
+            ("abc\n", ""),
+            ("one\ntwo\nthree\n", "one\n2\nthree\nfour\n"),
+            ("a\nb\nc\nd\ne\n", "b\nc\nX\ne\nf"),
+            ("line one\r\nline two\r\n", "line one\rline 2\r\n"),
+            ("x\r\ny", "x\ny"),
+            ("fn é() {}\n// 𝄞\n", "fn ö() {}\n// 𝄞𝄞\n"),
+        ];
+        for (old, new) in cases {
+            let changes = diff(old, new, PositionEncoding::Utf16);
+            assert_eq!(apply(old, &changes), new, "{:?} -> {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn test_diff_is_minimal() {
+        let old = "fn main() {\n    let x = 1;\n    let y = 2;\n}\n";
+        let new = "fn main() {\n    let x = 10;\n    let y = 2;\n    y\n}\n";
+        let changes = diff(old, new, PositionEncoding::Utf16);
+        let edits: Vec<_> = changes
+            .iter()
+            .map(|c| {
+                let range = c.range.unwrap();
+                (
+                    (range.start.line, range.start.character),
+                    (range.end.line, range.end.character),
+                    c.text.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            edits,
+            [((3, 0), (3, 0), "    y\n"), ((1, 13), (1, 13), "0")]
+        );
+        assert!(diff(old, old, PositionEncoding::Utf16).is_empty());
+    }
+}
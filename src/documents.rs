@@ -0,0 +1,709 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! Keeps the server's view of open text documents in step with the client's.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument};
+use lsp_types::{
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url, VersionedTextDocumentIdentifier,
+};
+use serde_json::value::Value;
+
+use crate::client::{LanguageServerRef, WeakLanguageServerRef};
+use crate::diff::diff;
+use crate::error::ClientError;
+use crate::position::{LineIndex, PositionEncoding};
+use crate::timer;
+
+/// A document the client has opened, as last sent to the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextDocument {
+    uri: Url,
+    language_id: String,
+    version: i32,
+    text: String,
+}
+
+impl TextDocument {
+    pub fn uri(&self) -> &Url {
+        &self.uri
+    }
+
+    pub fn language_id(&self) -> &str {
+        &self.language_id
+    }
+
+    /// The version of the document, which increases with every change.
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// An index for converting between positions and offsets in the text.
+    pub fn line_index(&self) -> LineIndex<'_> {
+        LineIndex::new(&self.text)
+    }
+
+    /// Applies a change, replacing the whole text if it has no range.
+    fn apply(&mut self, change: &TextDocumentContentChangeEvent, encoding: PositionEncoding) {
+        match change.range {
+            Some(range) => {
+                let index = self.line_index();
+                let start = index.offset(range.start, encoding);
+                let end = index.offset(range.end, encoding).max(start);
+                self.text.replace_range(start..end, &change.text);
+            }
+            None => self.text = change.text.clone(),
+        }
+    }
+}
+
+/// How the server wants a document synchronized.
+struct SyncOptions {
+    open: bool,
+    close: bool,
+    change: TextDocumentSyncKind,
+}
+
+impl SyncOptions {
+    /// The options for `document`, from the server's `textDocumentSync` capability and any
+    /// dynamic registrations of the sync notifications whose document selector matches it.
+    ///
+    /// Until the server's capabilities are known, everything is sent in full.
+    fn for_document<W: 'static + Write + Send>(
+        server: &LanguageServerRef<W>,
+        document: &TextDocument,
+    ) -> Self {
+        let sync = match server.capabilities() {
+            Some(capabilities) => capabilities.text_document_sync,
+            None => Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        };
+        let mut options = match sync {
+            Some(TextDocumentSyncCapability::Kind(kind)) => SyncOptions {
+                open: kind != TextDocumentSyncKind::NONE,
+                close: kind != TextDocumentSyncKind::NONE,
+                change: kind,
+            },
+            Some(TextDocumentSyncCapability::Options(options)) => SyncOptions {
+                open: options.open_close.unwrap_or(false),
+                close: options.open_close.unwrap_or(false),
+                change: options.change.unwrap_or(TextDocumentSyncKind::NONE),
+            },
+            None => SyncOptions {
+                open: false,
+                close: false,
+                change: TextDocumentSyncKind::NONE,
+            },
+        };
+        for registration in server.registrations() {
+            let register_options = registration.register_options.unwrap_or_default();
+            if !selector_matches(&register_options["documentSelector"], document) {
+                continue;
+            }
+            match registration.method.as_str() {
+                "textDocument/didOpen" => options.open = true,
+                "textDocument/didClose" => options.close = true,
+                "textDocument/didChange" => {
+                    let kind = serde_json::from_value(register_options["syncKind"].clone());
+                    if let Ok(kind) = kind {
+                        options.change = kind;
+                    }
+                }
+                _ => (),
+            }
+        }
+        options
+    }
+}
+
+/// Whether a registration's document selector applies to `document`. A missing selector
+/// applies to every document. Filters' glob patterns are not checked, so a filter that
+/// matches on its language and scheme matches any path.
+fn selector_matches(selector: &Value, document: &TextDocument) -> bool {
+    let filters = match selector.as_array() {
+        Some(filters) => filters,
+        None => return true,
+    };
+    filters.iter().any(|filter| {
+        let matches = |key: &str, value: &str| {
+            filter[key]
+                .as_str()
+                .is_none_or(|expected| expected == value)
+        };
+        matches("language", &document.language_id) && matches("scheme", document.uri.scheme())
+    })
+}
+
+/// Changes that have been applied to a document but not yet sent to the server.
+#[derive(Default)]
+struct Pending {
+    changes: Vec<TextDocumentContentChangeEvent>,
+    /// Identifies the most recently scheduled flush, so that earlier ones do nothing.
+    generation: u64,
+}
+
+struct Documents {
+    open: HashMap<Url, TextDocument>,
+    pending: HashMap<Url, Pending>,
+    debounce: Option<Duration>,
+    next_generation: u64,
+}
+
+impl Documents {
+    /// Sends the pending changes to `uri`, or to every document if there is no `uri`.
+    fn flush<W: 'static + Write + Send>(
+        &mut self,
+        server: &LanguageServerRef<W>,
+        uri: Option<&Url>,
+    ) -> Result<(), ClientError> {
+        let uris: Vec<Url> = match uri {
+            Some(uri) => vec![uri.clone()],
+            None => self.pending.keys().cloned().collect(),
+        };
+        for uri in uris {
+            let pending = match self.pending.remove(&uri) {
+                Some(pending) => pending,
+                None => continue,
+            };
+            if let Some(document) = self.open.get(&uri) {
+                send_changes(server, document, pending.changes)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends `textDocument/didChange` for changes that brought `document` to its current version,
+/// either as they are or as the full text, as the server asks.
+fn send_changes<W: 'static + Write + Send>(
+    server: &LanguageServerRef<W>,
+    document: &TextDocument,
+    changes: Vec<TextDocumentContentChangeEvent>,
+) -> Result<(), ClientError> {
+    let content_changes = match SyncOptions::for_document(server, document).change {
+        TextDocumentSyncKind::INCREMENTAL => changes,
+        TextDocumentSyncKind::FULL => vec![TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: document.text.clone(),
+        }],
+        _ => return Ok(()),
+    };
+    server.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier {
+            uri: document.uri.clone(),
+            version: document.version,
+        },
+        content_changes,
+    })
+}
+
+/// The documents open in a language server.
+///
+/// Opening, changing and closing documents through the store sends `textDocument/didOpen`,
+/// `textDocument/didChange` and `textDocument/didClose` as the server's `textDocumentSync`
+/// capability, or its dynamic registrations of them, ask: changes are sent incrementally or
+/// in full, or not at all. Versions are
+/// assigned by the store, starting at 1 when a document is opened.
+///
+/// With [`set_debounce`](Self::set_debounce), changes made in quick succession are merged
+/// into a single `textDocument/didChange`. All pending changes are sent before any request,
+/// since the server may answer it from any of the documents, and debounced changes are sent
+/// from a thread of their own so that a slow write does not hold up other timers.
+pub struct DocumentStore<W: Write> {
+    server: LanguageServerRef<W>,
+    documents: Arc<Mutex<Documents>>,
+}
+
+impl<W: Write> Clone for DocumentStore<W> {
+    fn clone(&self) -> Self {
+        DocumentStore {
+            server: self.server.clone(),
+            documents: self.documents.clone(),
+        }
+    }
+}
+
+/// The [`DocumentStore`] counterpart of [`WeakLanguageServerRef`].
+pub(crate) struct WeakDocumentStore<W: Write> {
+    server: WeakLanguageServerRef<W>,
+    documents: Weak<Mutex<Documents>>,
+}
+
+impl<W: Write> WeakDocumentStore<W> {
+    pub(crate) fn upgrade(&self) -> Option<DocumentStore<W>> {
+        Some(DocumentStore {
+            server: self.server.upgrade()?,
+            documents: self.documents.upgrade()?,
+        })
+    }
+}
+
+impl<W: 'static + Write + Send> DocumentStore<W> {
+    pub fn new(server: LanguageServerRef<W>) -> Self {
+        let documents = Arc::new(Mutex::new(Documents {
+            open: HashMap::new(),
+            pending: HashMap::new(),
+            debounce: None,
+            next_generation: 0,
+        }));
+
+        let lang_server = server.downgrade();
+        let weak = Arc::downgrade(&documents);
+        server.add_request_hook(move |_, _| {
+            let (lang_server, documents) = match (lang_server.upgrade(), weak.upgrade()) {
+                (Some(lang_server), Some(documents)) => (lang_server, documents),
+                _ => return,
+            };
+            let mut documents = documents.lock().unwrap();
+            if let Err(err) = documents.flush(&lang_server, None) {
+                lang_server.report_error(err);
+            }
+        });
+
+        DocumentStore { server, documents }
+    }
+
+    pub fn server(&self) -> &LanguageServerRef<W> {
+        &self.server
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakDocumentStore<W> {
+        WeakDocumentStore {
+            server: self.server.downgrade(),
+            documents: Arc::downgrade(&self.documents),
+        }
+    }
+
+    /// Sets how long to wait for further changes to a document before sending them, or
+    /// `None` to send every change immediately, which is the default.
+    ///
+    /// Each change restarts the wait. Turning debouncing off sends any pending changes.
+    pub fn set_debounce(&self, window: Option<Duration>) -> Result<(), ClientError> {
+        let mut documents = self.documents.lock().unwrap();
+        documents.debounce = window;
+        if window.is_none() {
+            documents.flush(&self.server, None)?;
+        }
+        Ok(())
+    }
+
+    /// Sends any pending changes to `uri` now.
+    pub fn flush(&self, uri: &Url) -> Result<(), ClientError> {
+        let mut documents = self.documents.lock().unwrap();
+        documents.flush(&self.server, Some(uri))
+    }
+
+    /// Sends all pending changes now.
+    pub fn flush_all(&self) -> Result<(), ClientError> {
+        let mut documents = self.documents.lock().unwrap();
+        documents.flush(&self.server, None)
+    }
+
+    /// A copy of the open document at `uri`.
+    pub fn get(&self, uri: &Url) -> Option<TextDocument> {
+        self.documents.lock().unwrap().open.get(uri).cloned()
+    }
+
+    /// The current version of the open document at `uri`.
+    pub fn version(&self, uri: &Url) -> Option<i32> {
+        let documents = self.documents.lock().unwrap();
+        documents.open.get(uri).map(|document| document.version)
+    }
+
+    pub fn is_open(&self, uri: &Url) -> bool {
+        self.documents.lock().unwrap().open.contains_key(uri)
+    }
+
+    /// The URIs of all open documents.
+    pub fn uris(&self) -> Vec<Url> {
+        self.documents
+            .lock()
+            .unwrap()
+            .open
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Opens a document, sending `textDocument/didOpen`.
+    pub fn open(&self, uri: Url, language_id: &str, text: String) -> Result<(), ClientError> {
+        let mut documents = self.documents.lock().unwrap();
+        if documents.open.contains_key(&uri) {
+            return Err(ClientError::DocumentAlreadyOpen(uri));
+        }
+        let document = TextDocument {
+            uri: uri.clone(),
+            language_id: language_id.to_owned(),
+            version: 1,
+            text,
+        };
+        if SyncOptions::for_document(&self.server, &document).open {
+            self.server
+                .notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                    text_document: TextDocumentItem {
+                        uri: uri.clone(),
+                        language_id: document.language_id.clone(),
+                        version: document.version,
+                        text: document.text.clone(),
+                    },
+                })?;
+        }
+        documents.open.insert(uri, document);
+        Ok(())
+    }
+
+    /// Applies `changes` to an open document in order, and sends them to the server.
+    /// Returns the document's new version.
+    ///
+    /// Ranges are in the negotiated position encoding. If the server only accepts full
+    /// updates, the resulting text is sent instead.
+    pub fn change(
+        &self,
+        uri: &Url,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Result<i32, ClientError> {
+        let mut documents = self.documents.lock().unwrap();
+        let document = documents
+            .open
+            .get_mut(uri)
+            .ok_or_else(|| ClientError::DocumentNotOpen(uri.clone()))?;
+        let encoding = self.server.position_encoding();
+        for change in &changes {
+            document.apply(change, encoding);
+        }
+        self.record_changes(&mut documents, uri, changes)
+    }
+
+    /// Replaces the whole text of an open document, sending the server only the parts that
+    /// differ. Returns the document's new version, which is unchanged if the text is.
+    pub fn set_text(&self, uri: &Url, text: String) -> Result<i32, ClientError> {
+        let mut documents = self.documents.lock().unwrap();
+        let document = documents
+            .open
+            .get_mut(uri)
+            .ok_or_else(|| ClientError::DocumentNotOpen(uri.clone()))?;
+        let changes = diff(&document.text, &text, self.server.position_encoding());
+        if changes.is_empty() {
+            return Ok(document.version);
+        }
+        document.text = text;
+        self.record_changes(&mut documents, uri, changes)
+    }
+
+    /// Bumps the version of a document that has just changed, and sends `changes` now or
+    /// once the debounce window has passed.
+    fn record_changes(
+        &self,
+        documents: &mut Documents,
+        uri: &Url,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Result<i32, ClientError> {
+        let document = documents.open.get_mut(uri).unwrap();
+        document.version += 1;
+        let version = document.version;
+
+        documents.next_generation += 1;
+        let generation = documents.next_generation;
+        let pending = documents.pending.entry(uri.clone()).or_default();
+        pending.changes.extend(changes);
+        pending.generation = generation;
+
+        let delay = match documents.debounce {
+            Some(delay) => delay,
+            None => {
+                documents.flush(&self.server, Some(uri))?;
+                return Ok(version);
+            }
+        };
+        let lang_server = self.server.downgrade();
+        let weak = Arc::downgrade(&self.documents);
+        let uri = uri.clone();
+        timer::schedule(delay, move || {
+            // the write may block, so it must not hold up the timer thread
+            thread::spawn(move || {
+                let (lang_server, documents) = match (lang_server.upgrade(), weak.upgrade()) {
+                    (Some(lang_server), Some(documents)) => (lang_server, documents),
+                    _ => return,
+                };
+                let mut documents = documents.lock().unwrap();
+                match documents.pending.get(&uri) {
+                    Some(pending) if pending.generation == generation => (),
+                    _ => return,
+                }
+                if let Err(err) = documents.flush(&lang_server, Some(&uri)) {
+                    lang_server.report_error(err);
+                }
+            });
+        });
+        Ok(version)
+    }
+
+    /// Closes a document, sending any pending changes and then `textDocument/didClose`.
+    pub fn close(&self, uri: &Url) -> Result<(), ClientError> {
+        let mut documents = self.documents.lock().unwrap();
+        if !documents.open.contains_key(uri) {
+            return Err(ClientError::DocumentNotOpen(uri.clone()));
+        }
+        let flushed = documents.flush(&self.server, Some(uri));
+        let document = documents.open.remove(uri).unwrap();
+        flushed?;
+        if SyncOptions::for_document(&self.server, &document).close {
+            self.server
+                .notify::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+                    text_document: TextDocumentIdentifier { uri: uri.clone() },
+                })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Position, Range};
+
+    use super::*;
+    use crate::client::tests::{initialize, SharedBuffer};
+
+    fn edit(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(
+                Position::new(start.0, start.1),
+                Position::new(end.0, end.1),
+            )),
+            range_length: None,
+            text: text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_incremental_sync() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        initialize(&lang_server, json!({"textDocumentSync": 2}));
+        let store = DocumentStore::new(lang_server);
+        let uri = Url::parse("file:///a.rs").unwrap();
+
+        let change = vec![edit((0, 0), (0, 0), "x")];
+        assert!(matches!(
+            store.change(&uri, change.clone()),
+            Err(ClientError::DocumentNotOpen(_))
+        ));
+        store
+            .open(uri.clone(), "rust", "fn main() {}\n".into())
+            .unwrap();
+        assert!(store.open(uri.clone(), "rust", String::new()).is_err());
+
+        let version = store
+            .change(
+                &uri,
+                vec![edit((0, 3), (0, 7), "start"), edit((1, 0), (1, 0), "//")],
+            )
+            .unwrap();
+        assert_eq!(version, 2);
+        assert_eq!(store.get(&uri).unwrap().text(), "fn start() {}\n//");
+        assert_eq!(
+            store.set_text(&uri, "fn start() {}\n//!".into()).unwrap(),
+            3
+        );
+        assert_eq!(
+            store.set_text(&uri, "fn start() {}\n//!".into()).unwrap(),
+            3
+        );
+        store.close(&uri).unwrap();
+        assert!(!store.is_open(&uri));
+
+        let messages = buffer.messages();
+        assert_eq!(messages[1]["method"], "textDocument/didOpen");
+        assert_eq!(messages[1]["params"]["textDocument"]["version"], 1);
+        assert_eq!(messages[2]["method"], "textDocument/didChange");
+        assert_eq!(messages[2]["params"]["textDocument"]["version"], 2);
+        assert_eq!(messages[2]["params"]["contentChanges"][0]["text"], "start");
+        assert_eq!(
+            messages[3]["params"]["contentChanges"],
+            json!([{
+                "range": {"start": {"line": 1, "character": 2}, "end": {"line": 1, "character": 2}},
+                "text": "!",
+            }])
+        );
+        assert_eq!(messages[4]["method"], "textDocument/didClose");
+    }
+
+    #[test]
+    fn test_changes_use_negotiated_encoding() {
+        let uri = Url::parse("file:///a.txt").unwrap();
+        for (encoding, character) in [("utf-8", 6), ("utf-16", 2), ("utf-32", 2)] {
+            let lang_server = LanguageServerRef::new(Vec::new(), None);
+            initialize(&lang_server, json!({"positionEncoding": encoding}));
+            let store = DocumentStore::new(lang_server);
+            store.open(uri.clone(), "plaintext", "€€€".into()).unwrap();
+            store
+                .change(&uri, vec![edit((0, character), (0, character), "|")])
+                .unwrap();
+            assert_eq!(store.get(&uri).unwrap().text(), "€€|€");
+        }
+    }
+
+    #[test]
+    fn test_full_and_no_sync() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        initialize(
+            &lang_server,
+            json!({"textDocumentSync": {"openClose": true, "change": 1}}),
+        );
+        let store = DocumentStore::new(lang_server);
+        let uri = Url::parse("file:///a.txt").unwrap();
+        store
+            .open(uri.clone(), "plaintext", "one two".into())
+            .unwrap();
+        store.change(&uri, vec![edit((0, 3), (0, 7), "")]).unwrap();
+        let change = &buffer.messages()[2];
+        assert_eq!(change["params"]["contentChanges"], json!([{"text": "one"}]));
+
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        initialize(&lang_server, json!({}));
+        let store = DocumentStore::new(lang_server);
+        store.open(uri.clone(), "plaintext", "one".into()).unwrap();
+        assert_eq!(store.set_text(&uri, "two".into()).unwrap(), 2);
+        store.close(&uri).unwrap();
+        assert_eq!(buffer.messages().len(), 1);
+    }
+
+    #[test]
+    fn test_dynamic_sync_registrations() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        initialize(&lang_server, json!({}));
+        let selector = json!([{"language": "rust"}]);
+        let register = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "client/registerCapability",
+            "params": {"registrations": [
+                {"id": "1", "method": "textDocument/didOpen",
+                 "registerOptions": {"documentSelector": selector}},
+                {"id": "2", "method": "textDocument/didChange",
+                 "registerOptions": {"documentSelector": selector, "syncKind": 2}},
+            ]},
+        });
+        lang_server.handle_msg(&register).unwrap();
+        let store = DocumentStore::new(lang_server);
+        let sent = || buffer.messages().len();
+        let before = sent();
+
+        // only documents the selector matches are synchronized
+        let text = Url::parse("file:///a.txt").unwrap();
+        store.open(text.clone(), "plaintext", "one".into()).unwrap();
+        store.set_text(&text, "two".into()).unwrap();
+        assert_eq!(sent(), before);
+
+        let uri = Url::parse("file:///a.rs").unwrap();
+        store.open(uri.clone(), "rust", "fn a() {}".into()).unwrap();
+        store.change(&uri, vec![edit((0, 3), (0, 4), "b")]).unwrap();
+        // didClose was not registered
+        store.close(&uri).unwrap();
+        let messages = buffer.messages();
+        let methods: Vec<_> = messages[before..]
+            .iter()
+            .map(|m| m["method"].as_str().unwrap())
+            .collect();
+        assert_eq!(methods, ["textDocument/didOpen", "textDocument/didChange"]);
+        assert!(messages[before + 1]["params"]["contentChanges"][0]["range"].is_object());
+    }
+
+    #[test]
+    fn test_debounced_changes() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        initialize(&lang_server, json!({"textDocumentSync": 2}));
+        let store = DocumentStore::new(lang_server.clone());
+        let uri = Url::parse("file:///a.rs").unwrap();
+        let other = Url::parse("file:///b.rs").unwrap();
+        store.open(uri.clone(), "rust", String::new()).unwrap();
+        store.open(other.clone(), "rust", String::new()).unwrap();
+
+        // long enough that only the request sends the changes
+        store.set_debounce(Some(Duration::from_secs(60))).unwrap();
+        for (i, c) in ["a", "b", "c"].into_iter().enumerate() {
+            let i = i as u32;
+            store.change(&uri, vec![edit((0, i), (0, i), c)]).unwrap();
+        }
+        store.set_text(&other, "b".into()).unwrap();
+        assert_eq!(buffer.messages().len(), 3);
+        // changes to every document are sent, not just the one the request names
+        let params = json!({"textDocument": {"uri": uri}, "position": {"line": 0, "character": 0}});
+        lang_server
+            .send_request("textDocument/hover", &params, |_| ())
+            .unwrap();
+        let messages = buffer.messages();
+        let mut changed: Vec<_> = messages[3..5]
+            .iter()
+            .map(|m| {
+                assert_eq!(m["method"], "textDocument/didChange");
+                let document = &m["params"]["textDocument"];
+                let changes = m["params"]["contentChanges"].as_array().unwrap().len();
+                (
+                    document["uri"].clone(),
+                    document["version"].clone(),
+                    changes,
+                )
+            })
+            .collect();
+        changed.sort_by_key(|(uri, _, _)| uri.as_str().unwrap().to_owned());
+        assert_eq!(
+            changed,
+            [
+                (json!(uri.as_str()), json!(4), 3),
+                (json!(other.as_str()), json!(2), 1),
+            ]
+        );
+        assert_eq!(messages[5]["method"], "textDocument/hover");
+
+        store.set_debounce(Some(Duration::from_millis(20))).unwrap();
+        store.set_text(&uri, "abcd".into()).unwrap();
+        store.set_text(&uri, "abcde".into()).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        let messages = buffer.messages();
+        assert_eq!(messages.len(), 7);
+        assert_eq!(messages[6]["params"]["textDocument"]["version"], 6);
+        assert_eq!(
+            messages[6]["params"]["contentChanges"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! Error types shared by the client.
+
+use std::{fmt, io};
+
+use lsp_types::Url;
+use serde_json::value::Value;
+
+use crate::lifecycle::LifecycleState;
+use crate::parsing::ParseError;
+
+/// Errors raised by the client itself, as opposed to errors reported by the server.
+#[derive(Debug)]
+pub enum ClientError {
+    /// Reading from or writing to the server failed.
+    Io(io::Error),
+    /// An outgoing message could not be serialized.
+    Encoding(serde_json::Error),
+    /// An incoming message could not be framed or decoded.
+    Parse(ParseError),
+    /// The server responded to a request that is not pending.
+    UnknownResponseId(Value),
+    /// The server sent a message that does not follow JSON-RPC or the protocol.
+    Protocol(String),
+    /// The server has closed its output; no further messages can be exchanged.
+    Disconnected,
+    /// The server has not advertised support for the request's method.
+    Unsupported(String),
+    /// The document has not been opened.
+    DocumentNotOpen(Url),
+    /// The document is already open.
+    DocumentAlreadyOpen(Url),
+    /// The protocol does not allow `method` to be sent in the server's current state.
+    Lifecycle {
+        method: String,
+        state: LifecycleState,
+    },
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> ClientError {
+        ClientError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(err: serde_json::Error) -> ClientError {
+        ClientError::Encoding(err)
+    }
+}
+
+impl From<ParseError> for ClientError {
+    fn from(err: ParseError) -> ClientError {
+        ClientError::Parse(err)
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Io(err) => write!(f, "transport error: {}", err),
+            ClientError::Encoding(err) => write!(f, "error encoding message: {}", err),
+            ClientError::Parse(err) => write!(f, "error parsing message: {:?}", err),
+            ClientError::UnknownResponseId(id) => write!(f, "response for unknown id {}", id),
+            ClientError::Protocol(msg) => write!(f, "protocol violation: {}", msg),
+            ClientError::Disconnected => write!(f, "server disconnected"),
+            ClientError::Unsupported(method) => {
+                write!(f, "server does not support {}", method)
+            }
+            ClientError::DocumentNotOpen(uri) => write!(f, "{} is not open", uri),
+            ClientError::DocumentAlreadyOpen(uri) => write!(f, "{} is already open", uri),
+            ClientError::Lifecycle { method, state } => {
+                write!(f, "cannot send {} while the server is {}", method, state)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(err) => Some(err),
+            ClientError::Encoding(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Error codes defined by JSON-RPC and the Language Server Protocol, along with the
+/// codes used for errors that the client synthesizes itself.
+pub mod codes {
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+    pub const REQUEST_CANCELLED: i64 = -32800;
+
+    /// The server exited or closed its output before responding to a request.
+    ///
+    /// This lies in the range JSON-RPC reserves for implementation-defined errors.
+    pub const SERVER_DISCONNECTED: i64 = -32099;
+    /// The server did not respond to a request within its timeout.
+    pub const REQUEST_TIMED_OUT: i64 = -32098;
+}
+
+/// The `error` member of a JSON-RPC response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl ResponseError {
+    pub fn new<S: Into<String>>(code: i64, message: S) -> Self {
+        ResponseError {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// The error sent in response to a request for a method the client does not handle.
+    pub fn method_not_found(method: &str) -> Self {
+        ResponseError::new(
+            codes::METHOD_NOT_FOUND,
+            format!("method not found: {}", method),
+        )
+    }
+
+    pub fn invalid_params<S: Into<String>>(message: S) -> Self {
+        ResponseError::new(codes::INVALID_PARAMS, message)
+    }
+
+    pub fn internal_error<S: Into<String>>(message: S) -> Self {
+        ResponseError::new(codes::INTERNAL_ERROR, message)
+    }
+
+    /// The error used to complete requests that were pending when the server went away.
+    pub fn server_disconnected() -> Self {
+        ResponseError::new(codes::SERVER_DISCONNECTED, "server disconnected")
+    }
+
+    /// The error used to complete requests that the server did not answer in time.
+    pub fn request_timed_out() -> Self {
+        ResponseError::new(codes::REQUEST_TIMED_OUT, "request timed out")
+    }
+
+    /// The error used to complete requests cancelled through a
+    /// [`RequestHandle`](crate::client::RequestHandle).
+    pub fn request_cancelled() -> Self {
+        ResponseError::new(codes::REQUEST_CANCELLED, "request cancelled")
+    }
+
+    /// Extracts the error from a JSON-RPC error response, as passed to the `Err` arm of a
+    /// request callback. A bare error object is also accepted.
+    pub fn from_response(response: &Value) -> Option<Self> {
+        let error = response.get("error").unwrap_or(response);
+        let code = error.get("code")?.as_i64()?;
+        let message = error.get("message")?.as_str()?;
+        Some(ResponseError {
+            code,
+            message: message.to_owned(),
+            data: error.get("data").cloned(),
+        })
+    }
+
+    /// Like [`from_response`](Self::from_response), but substitutes an internal error if
+    /// `response` does not carry a well-formed error.
+    pub(crate) fn from_error_response(response: &Value) -> Self {
+        ResponseError::from_response(response)
+            .unwrap_or_else(|| ResponseError::internal_error("malformed error response"))
+    }
+
+    /// Converts this error into the JSON object placed in a response's `error` member.
+    pub fn to_value(&self) -> Value {
+        let mut error = json!({
+            "code": self.code,
+            "message": self.message,
+        });
+        if let Some(ref data) = self.data {
+            error["data"] = data.clone();
+        }
+        error
+    }
+
+    /// Builds a complete JSON-RPC error response carrying this error.
+    pub(crate) fn to_response(&self, id: &Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": self.to_value(),
+        })
+    }
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for ResponseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_error_round_trip() {
+        let mut error = ResponseError::new(-32601, "no such method");
+        error.data = Some(json!({"method": "foo"}));
+        let response = error.to_response(&json!(7));
+        assert_eq!(response["id"], json!(7));
+        assert_eq!(ResponseError::from_response(&response), Some(error.clone()));
+        assert_eq!(ResponseError::from_response(&error.to_value()), Some(error));
+    }
+
+    #[test]
+    fn test_response_error_from_malformed() {
+        assert_eq!(ResponseError::from_response(&json!({"error": {}})), None);
+        assert_eq!(ResponseError::from_response(&json!(null)), None);
+    }
+}
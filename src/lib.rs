@@ -24,8 +24,37 @@
 extern crate serde_json;
 extern crate jsonrpc_lite;
 
+pub use lsp_types;
+
 #[macro_use]
 mod parsing;
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod capabilities;
 pub mod client;
+pub mod diagnostics;
+pub mod diff;
+pub mod documents;
+pub mod error;
+pub mod lifecycle;
+pub mod position;
+pub mod progress;
+mod timer;
+pub mod transport;
+pub mod typed;
+pub mod workspace_edit;
 
-pub use client::{start_language_server, LanguageServerRef};
+pub use client::{
+    start_language_server, LanguageServerRef, Notification, RequestHandle, Subscription,
+};
+pub use diagnostics::{DiagnosticFilter, DiagnosticPuller, DiagnosticStore, DiagnosticsChange};
+pub use documents::{DocumentStore, TextDocument};
+pub use error::{ClientError, ResponseError};
+pub use lifecycle::{LifecycleManager, LifecycleState, PendingRequest, QueuedRequest};
+pub use parsing::{MessageHeaders, ParseError};
+pub use position::{LineIndex, PositionEncoding};
+pub use progress::{Progress, ProgressTracker};
+pub use transport::{connect, connect_tcp, spawn_reverse, Backoff, ListenAddress, Transport};
+#[cfg(unix)]
+pub use transport::{connect_fifos, connect_unix};
+pub use workspace_edit::{EditError, WorkspaceEditor};
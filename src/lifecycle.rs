@@ -0,0 +1,636 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! Enforces the initialize / initialized / shutdown / exit sequence required by the
+//! protocol.
+
+use std::fmt;
+use std::io::Write;
+use std::process::ExitStatus;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lsp_types::notification::{Exit, Initialized};
+use lsp_types::request::{Initialize, Shutdown};
+use lsp_types::{ClientCapabilities, InitializeParams, InitializeResult, InitializedParams};
+use serde_json::value::Value;
+
+use crate::capabilities::ClientCapabilitiesBuilder;
+use crate::client::{LanguageServerRef, RequestHandle};
+use crate::error::{ClientError, ResponseError};
+use crate::typed::{typed_result, TypedError};
+
+/// Where a server is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleState {
+    /// `initialize` has not been sent.
+    Uninitialized,
+    /// `initialize` has been sent, and the server has not yet responded.
+    Initializing,
+    /// The server is initialized, and accepts any request.
+    Running,
+    /// `shutdown` has been sent; only `exit` may follow.
+    ShuttingDown,
+    /// `exit` has been sent, or the server has disconnected.
+    Exited,
+}
+
+impl fmt::Display for LifecycleState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self {
+            LifecycleState::Uninitialized => "uninitialized",
+            LifecycleState::Initializing => "initializing",
+            LifecycleState::Running => "running",
+            LifecycleState::ShuttingDown => "shutting down",
+            LifecycleState::Exited => "exited",
+        };
+        f.write_str(state)
+    }
+}
+
+type QueuedCallback = Box<dyn FnOnce(Result<Value, Value>) + Send>;
+
+/// Where a queued request has got to.
+enum Slot {
+    Waiting(QueuedCallback),
+    Sent(RequestHandle),
+    /// Cancelled, failed, or dropped on exit.
+    Done,
+}
+
+/// A request held back while the server is initializing. Once the server is running the
+/// request is sent, and can be followed through [`handle`](Self::handle).
+#[derive(Clone)]
+pub struct QueuedRequest {
+    slot: Arc<Mutex<Slot>>,
+}
+
+impl QueuedRequest {
+    /// The handle of the request, once it has been sent.
+    pub fn handle(&self) -> Option<RequestHandle> {
+        match &*self.slot.lock().unwrap() {
+            Slot::Sent(handle) => Some(handle.clone()),
+            _ => None,
+        }
+    }
+
+    /// Cancels the request. If it has not been sent yet it never will be, and its callback
+    /// receives a cancellation error right away; otherwise this is
+    /// [`RequestHandle::cancel`].
+    pub fn cancel(&self) {
+        let mut slot = self.slot.lock().unwrap();
+        if let Slot::Sent(handle) = &*slot {
+            let handle = handle.clone();
+            drop(slot);
+            handle.cancel();
+        } else if let Slot::Waiting(completion) = std::mem::replace(&mut *slot, Slot::Done) {
+            drop(slot);
+            completion(Err(
+                ResponseError::request_cancelled().to_response(&Value::Null)
+            ));
+        }
+    }
+}
+
+impl fmt::Debug for QueuedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QueuedRequest")
+            .field("handle", &self.handle())
+            .finish()
+    }
+}
+
+/// A request accepted by a [`LifecycleManager`].
+#[derive(Debug, Clone)]
+pub enum PendingRequest {
+    /// The request was sent to the running server.
+    Sent(RequestHandle),
+    /// The request is queued until the server has initialized.
+    Queued(QueuedRequest),
+}
+
+impl PendingRequest {
+    /// Cancels the request, whether or not it has been sent yet.
+    pub fn cancel(&self) {
+        match self {
+            PendingRequest::Sent(handle) => handle.cancel(),
+            PendingRequest::Queued(queued) => queued.cancel(),
+        }
+    }
+}
+
+/// A message sent while the server was initializing, to be sent once it is running.
+enum Queued {
+    Request {
+        method: String,
+        params: Value,
+        slot: Arc<Mutex<Slot>>,
+    },
+    Notification {
+        method: String,
+        params: Value,
+    },
+}
+
+struct Lifecycle {
+    state: LifecycleState,
+    initialize_result: Option<InitializeResult>,
+    queued: Vec<Queued>,
+}
+
+/// Wraps a [`LanguageServerRef`], tracking the server's lifecycle and holding back messages
+/// that the protocol does not allow in the current state.
+///
+/// While the server is initializing, requests and notifications are queued, and sent in
+/// order once it has responded to `initialize`. Before `initialize` and after `shutdown`,
+/// they are rejected with [`ClientError::Lifecycle`].
+pub struct LifecycleManager<W: Write> {
+    server: LanguageServerRef<W>,
+    lifecycle: Arc<Mutex<Lifecycle>>,
+}
+
+impl<W: Write> Clone for LifecycleManager<W> {
+    fn clone(&self) -> Self {
+        LifecycleManager {
+            server: self.server.clone(),
+            lifecycle: self.lifecycle.clone(),
+        }
+    }
+}
+
+impl<W: 'static + Write + Send> LifecycleManager<W> {
+    pub fn new(server: LanguageServerRef<W>) -> Self {
+        LifecycleManager {
+            server,
+            lifecycle: Arc::new(Mutex::new(Lifecycle {
+                state: LifecycleState::Uninitialized,
+                initialize_result: None,
+                queued: Vec::new(),
+            })),
+        }
+    }
+
+    /// The underlying server, for registering handlers and subscriptions. Messages sent
+    /// directly through it bypass the lifecycle checks.
+    pub fn server(&self) -> &LanguageServerRef<W> {
+        &self.server
+    }
+
+    pub fn state(&self) -> LifecycleState {
+        if !self.server.is_connected() {
+            return LifecycleState::Exited;
+        }
+        self.lifecycle.lock().unwrap().state
+    }
+
+    /// The server's response to `initialize`, once it has arrived.
+    pub fn initialize_result(&self) -> Option<InitializeResult> {
+        self.lifecycle.lock().unwrap().initialize_result.clone()
+    }
+
+    fn rejection(method: &str, state: LifecycleState) -> ClientError {
+        ClientError::Lifecycle {
+            method: method.to_owned(),
+            state,
+        }
+    }
+
+    /// Sends `initialize`. Once the server responds, its result is stored, `initialized` is
+    /// sent, and any queued messages follow; then `completion` is called with the result.
+    ///
+    /// If `params` leaves the capabilities empty, those of
+    /// [`ClientCapabilitiesBuilder::for_server`] are sent instead.
+    ///
+    /// If initialization fails, the state returns to `Uninitialized` and queued requests
+    /// receive the server's error.
+    pub fn initialize<CB>(
+        &self,
+        mut params: InitializeParams,
+        completion: CB,
+    ) -> Result<RequestHandle, ClientError>
+    where
+        CB: 'static + Send + FnOnce(Result<InitializeResult, TypedError>),
+    {
+        let mut lifecycle = self.lifecycle.lock().unwrap();
+        if lifecycle.state != LifecycleState::Uninitialized {
+            return Err(Self::rejection("initialize", lifecycle.state));
+        }
+        if params.capabilities == ClientCapabilities::default() {
+            params.capabilities = ClientCapabilitiesBuilder::for_server(&self.server).build();
+        }
+        let params = serde_json::to_value(params)?;
+        let manager = self.clone();
+        let handle = self
+            .server
+            .send_request("initialize", &params, move |result| {
+                let error = result.as_ref().err().cloned();
+                let result = typed_result::<Initialize>(result);
+                manager.finish_initialize(result.as_ref().ok(), error);
+                completion(result)
+            })?;
+        lifecycle.state = LifecycleState::Initializing;
+        Ok(handle)
+    }
+
+    /// Sends a request that was queued while the server initialized. If it cannot be sent,
+    /// its completion is handed back with the error.
+    fn send_queued(
+        &self,
+        method: &str,
+        params: &Value,
+        completion: QueuedCallback,
+    ) -> Result<RequestHandle, (ClientError, Option<QueuedCallback>)> {
+        let completion = Arc::new(Mutex::new(Some(completion)));
+        let callback = completion.clone();
+        self.server
+            .send_request(method, params, move |result| {
+                if let Some(callback) = callback.lock().unwrap().take() {
+                    callback(result)
+                }
+            })
+            .map_err(|err| (err, completion.lock().unwrap().take()))
+    }
+
+    /// Records the outcome of `initialize` and releases the queued messages.
+    fn finish_initialize(&self, result: Option<&InitializeResult>, error: Option<Value>) {
+        let mut lifecycle = self.lifecycle.lock().unwrap();
+        if lifecycle.state != LifecycleState::Initializing {
+            return;
+        }
+        let queued = std::mem::take(&mut lifecycle.queued);
+        let mut failed = Vec::new();
+        match result {
+            Some(result) => {
+                lifecycle.state = LifecycleState::Running;
+                lifecycle.initialize_result = Some(result.clone());
+                if let Err(err) = self.server.notify::<Initialized>(InitializedParams {}) {
+                    self.server.report_error(err);
+                }
+                for message in queued {
+                    let sent = match message {
+                        Queued::Request {
+                            method,
+                            params,
+                            slot,
+                        } => {
+                            // held while sending, so that a cancel waits for the handle
+                            let mut slot = slot.lock().unwrap();
+                            match std::mem::replace(&mut *slot, Slot::Done) {
+                                Slot::Waiting(completion) => {
+                                    match self.send_queued(&method, &params, completion) {
+                                        Ok(handle) => {
+                                            *slot = Slot::Sent(handle);
+                                            Ok(())
+                                        }
+                                        Err((err, completion)) => {
+                                            let message = err.to_string();
+                                            failed.extend(completion.map(|c| (c, message)));
+                                            Err(err)
+                                        }
+                                    }
+                                }
+                                _ => Ok(()),
+                            }
+                        }
+                        Queued::Notification { method, params } => {
+                            self.server.send_notification(&method, &params)
+                        }
+                    };
+                    if let Err(err) = sent {
+                        self.server.report_error(err);
+                    }
+                }
+                drop(lifecycle);
+                for (completion, message) in failed {
+                    completion(Err(
+                        ResponseError::internal_error(message).to_response(&Value::Null)
+                    ));
+                }
+            }
+            None => {
+                lifecycle.state = LifecycleState::Uninitialized;
+                let error = error.unwrap_or_else(|| {
+                    ResponseError::internal_error("initialize returned an invalid result")
+                        .to_response(&Value::Null)
+                });
+                drop(lifecycle);
+                for message in queued {
+                    if let Queued::Request { slot, .. } = message {
+                        let slot = std::mem::replace(&mut *slot.lock().unwrap(), Slot::Done);
+                        if let Slot::Waiting(completion) = slot {
+                            completion(Err(error.clone()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends a request if the server is running. While it is initializing, the request is
+    /// queued, to be sent once it has initialized; in any other state it is rejected. A
+    /// queued request that cannot be sent then fails with an internal error.
+    pub fn send_request<CB>(
+        &self,
+        method: &str,
+        params: &Value,
+        completion: CB,
+    ) -> Result<PendingRequest, ClientError>
+    where
+        CB: 'static + Send + FnOnce(Result<Value, Value>),
+    {
+        let mut lifecycle = self.lifecycle.lock().unwrap();
+        match lifecycle.state {
+            LifecycleState::Running if method != "initialize" && method != "shutdown" => self
+                .server
+                .send_request(method, params, completion)
+                .map(PendingRequest::Sent),
+            LifecycleState::Initializing if method != "initialize" && method != "shutdown" => {
+                let slot = Arc::new(Mutex::new(Slot::Waiting(Box::new(completion))));
+                lifecycle.queued.push(Queued::Request {
+                    method: method.to_owned(),
+                    params: params.clone(),
+                    slot: slot.clone(),
+                });
+                Ok(PendingRequest::Queued(QueuedRequest { slot }))
+            }
+            state => Err(Self::rejection(method, state)),
+        }
+    }
+
+    /// Sends a notification if the server is running, or queues it while the server is
+    /// initializing. `exit` is always allowed; use [`exit`](Self::exit) to send it.
+    pub fn send_notification(&self, method: &str, params: &Value) -> Result<(), ClientError> {
+        if method == "exit" {
+            return self.exit();
+        }
+        let mut lifecycle = self.lifecycle.lock().unwrap();
+        match lifecycle.state {
+            LifecycleState::Running => self.server.send_notification(method, params),
+            LifecycleState::Initializing => {
+                lifecycle.queued.push(Queued::Notification {
+                    method: method.to_owned(),
+                    params: params.clone(),
+                });
+                Ok(())
+            }
+            state => Err(Self::rejection(method, state)),
+        }
+    }
+
+    /// Sends `shutdown`. Only `exit` may be sent after this.
+    pub fn shutdown<CB>(&self, completion: CB) -> Result<RequestHandle, ClientError>
+    where
+        CB: 'static + Send + FnOnce(Result<(), TypedError>),
+    {
+        let mut lifecycle = self.lifecycle.lock().unwrap();
+        if lifecycle.state != LifecycleState::Running {
+            return Err(Self::rejection("shutdown", lifecycle.state));
+        }
+        let handle = self.server.request::<Shutdown, _>((), completion)?;
+        lifecycle.state = LifecycleState::ShuttingDown;
+        Ok(handle)
+    }
+
+    /// Sends `exit`, which the protocol allows in any state.
+    pub fn exit(&self) -> Result<(), ClientError> {
+        let mut lifecycle = self.lifecycle.lock().unwrap();
+        lifecycle.state = LifecycleState::Exited;
+        for message in lifecycle.queued.drain(..) {
+            if let Queued::Request { slot, .. } = message {
+                // dropping the callback closes any channel it delivers to
+                *slot.lock().unwrap() = Slot::Done;
+            }
+        }
+        self.server.notify::<Exit>(())
+    }
+
+    /// Shuts the server down and waits for its process to exit, killing it if it has not
+    /// exited by the time `timeout` has elapsed.
+    ///
+    /// Returns the exit status, or `None` if the server is not a process spawned by this
+    /// client. This blocks, so it must not be called from a handler or callback.
+    pub fn shutdown_and_exit(&self, timeout: Duration) -> Result<Option<ExitStatus>, ClientError> {
+        let deadline = Instant::now() + timeout;
+        if self.state() == LifecycleState::Running {
+            let (tx, rx) = mpsc::channel();
+            self.shutdown(move |result| {
+                let _ = tx.send(result);
+            })?;
+            // whatever the response, we're going to exit
+            let _ = rx.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+        }
+        if self.server.is_connected() {
+            // the server may have hung up in the meantime
+            let _ = self.exit();
+        }
+        self.lifecycle.lock().unwrap().state = LifecycleState::Exited;
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if let Some(status) = self.server.wait_for_exit(Some(remaining)) {
+            return Ok(Some(status));
+        }
+        self.server.kill()?;
+        Ok(self.server.wait_for_exit(None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::process::{Command, Stdio};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::client::tests::SharedBuffer;
+    use crate::error::codes;
+    use crate::{start_language_server, ProgressTracker};
+
+    #[test]
+    fn test_lifecycle_ordering() {
+        let buffer = SharedBuffer::default();
+        let manager = LifecycleManager::new(LanguageServerRef::new(buffer.clone(), None));
+        let _progress = ProgressTracker::new(manager.server());
+        assert_eq!(manager.state(), LifecycleState::Uninitialized);
+        let hover = manager.send_request("textDocument/hover", &json!({}), |_| ());
+        assert!(matches!(
+            hover,
+            Err(ClientError::Lifecycle {
+                state: LifecycleState::Uninitialized,
+                ..
+            })
+        ));
+
+        let (tx, rx) = mpsc::channel();
+        manager
+            .initialize(InitializeParams::default(), move |result| {
+                let _ = tx.send(result.is_ok());
+            })
+            .unwrap();
+        assert_eq!(manager.state(), LifecycleState::Initializing);
+        let queued = match manager.send_request("textDocument/hover", &json!({}), |_| ()) {
+            Ok(PendingRequest::Queued(queued)) => queued,
+            other => panic!("request was not queued: {:?}", other),
+        };
+        assert!(queued.handle().is_none());
+        let (cancelled_tx, cancelled_rx) = mpsc::channel();
+        let cancelled = manager
+            .send_request("textDocument/definition", &json!({}), move |result| {
+                let _ = cancelled_tx.send(result);
+            })
+            .unwrap();
+        cancelled.cancel();
+        let error = ResponseError::from_response(&cancelled_rx.recv().unwrap().unwrap_err());
+        assert_eq!(error, Some(ResponseError::request_cancelled()));
+        manager
+            .send_notification("textDocument/didOpen", &json!({}))
+            .unwrap();
+        let sent = buffer.messages();
+        assert_eq!(sent.len(), 1);
+        // empty capabilities are filled in with the defaults, and those of the handlers
+        let capabilities = &sent[0]["params"]["capabilities"];
+        assert_eq!(
+            capabilities["textDocument"]["publishDiagnostics"]["versionSupport"],
+            true
+        );
+        assert_eq!(capabilities["workspace"]["applyEdit"], false);
+        assert_eq!(capabilities["window"]["workDoneProgress"], true);
+
+        let response = json!({"jsonrpc": "2.0", "id": 1, "result": {"capabilities": {}}});
+        manager.server().handle_msg(&response).unwrap();
+        assert!(rx.recv().unwrap());
+        assert_eq!(manager.state(), LifecycleState::Running);
+        assert!(manager.initialize_result().is_some());
+        let methods: Vec<_> = buffer
+            .messages()
+            .iter()
+            .map(|m| m["method"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            methods,
+            [
+                "initialize",
+                "initialized",
+                "textDocument/hover",
+                "textDocument/didOpen"
+            ]
+        );
+        assert_eq!(queued.handle().map(|handle| handle.id()), Some(2));
+
+        assert!(manager
+            .initialize(InitializeParams::default(), |_| ())
+            .is_err());
+        manager.shutdown(|_| ()).unwrap();
+        assert_eq!(manager.state(), LifecycleState::ShuttingDown);
+        assert!(manager
+            .send_notification("textDocument/didClose", &json!({}))
+            .is_err());
+        manager.exit().unwrap();
+        assert_eq!(manager.state(), LifecycleState::Exited);
+    }
+
+    /// Accepts writes until `reject` is set.
+    struct Rejecting {
+        buffer: SharedBuffer,
+        reject: Arc<AtomicBool>,
+    }
+
+    impl Write for Rejecting {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.reject.load(Ordering::SeqCst) {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.buffer.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_queued_request_send_failure() {
+        let reject = Arc::new(AtomicBool::new(false));
+        let writer = Rejecting {
+            buffer: SharedBuffer::default(),
+            reject: reject.clone(),
+        };
+        let manager = LifecycleManager::new(LanguageServerRef::new(writer, None));
+        manager
+            .initialize(InitializeParams::default(), |_| ())
+            .unwrap();
+        let (tx, rx) = mpsc::channel();
+        manager
+            .send_request("textDocument/hover", &json!({}), move |result| {
+                let _ = tx.send(result);
+            })
+            .unwrap();
+
+        reject.store(true, Ordering::SeqCst);
+        let response = json!({"jsonrpc": "2.0", "id": 1, "result": {"capabilities": {}}});
+        manager.server().handle_msg(&response).unwrap();
+        let error = ResponseError::from_response(&rx.recv().unwrap().unwrap_err()).unwrap();
+        assert_eq!(error.code, codes::INTERNAL_ERROR);
+    }
+
+    #[test]
+    fn test_shutdown_and_exit() {
+        let child = Command::new("rust-analyzer")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to start language server");
+        let manager = LifecycleManager::new(start_language_server(child).unwrap());
+        let (tx, rx) = mpsc::channel();
+        manager
+            .initialize(InitializeParams::default(), move |result| {
+                let _ = tx.send(result.is_ok());
+            })
+            .unwrap();
+        assert!(rx.recv().unwrap());
+
+        let status = manager
+            .shutdown_and_exit(Duration::from_secs(10))
+            .unwrap()
+            .expect("server was not a process");
+        assert!(status.success());
+        assert_eq!(manager.state(), LifecycleState::Exited);
+    }
+
+    #[test]
+    fn test_shutdown_and_exit_kills_unresponsive_server() {
+        let child = Command::new("sh")
+            .args(["-c", "exec cat > /dev/null"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let manager = LifecycleManager::new(start_language_server(child).unwrap());
+        manager
+            .initialize(InitializeParams::default(), |_| ())
+            .unwrap();
+        let status = manager
+            .shutdown_and_exit(Duration::from_millis(100))
+            .unwrap()
+            .unwrap();
+        assert!(!status.success());
+    }
+}
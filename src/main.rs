@@ -20,34 +20,40 @@
 //OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 //SOFTWARE.
 
-#[macro_use]
-extern crate serde_json;
 extern crate lsp_client;
 
+use lsp_client::capabilities::ClientCapabilitiesBuilder;
+use lsp_client::lsp_types::notification::{Exit, Initialized};
+use lsp_client::lsp_types::request::{Initialize, Shutdown};
+use lsp_client::lsp_types::{InitializeParams, InitializedParams};
 use lsp_client::start_language_server;
 use std::process::{Child, Command, Stdio};
 
 /// An example of how to interact with a language server.
 #[cfg(not(tarpaulin_include))]
 fn main() {
-    let (mut child, lang_server) = start_language_server(prepare_command());
-    let init = json!({
-        "process_id": "Null",
-        "initialization_options": {},
-        "capabilities": {},
-    });
-    lang_server.send_request("initialize", &init, |result| {
-        println!("received response {:#?}", result);
-    });
-    let initialized = json!({});
-    lang_server.send_notification("initialized", &initialized);
-    let shutdown = json!(());
-    lang_server.send_request("shutdown", &shutdown, |result| {
-        println!("received response {:#?}", result);
-    });
-    let exit = json!({});
-    lang_server.send_notification("exit", &exit);
-    let _ = child.wait();
+    let lang_server =
+        start_language_server(prepare_command()).expect("failed to connect to language server");
+    let init = InitializeParams {
+        process_id: Some(std::process::id()),
+        capabilities: ClientCapabilitiesBuilder::new().build(),
+        ..Default::default()
+    };
+    lang_server
+        .request::<Initialize, _>(init, |result| {
+            println!("received response {:#?}", result);
+        })
+        .expect("failed to send initialize");
+    lang_server
+        .notify::<Initialized>(InitializedParams {})
+        .expect("failed to send initialized");
+    lang_server
+        .request::<Shutdown, _>((), |result| {
+            println!("received response {:#?}", result);
+        })
+        .expect("failed to send shutdown");
+    lang_server.notify::<Exit>(()).expect("failed to send exit");
+    let _ = lang_server.wait_for_exit(None);
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -82,75 +82,181 @@ impl From<String> for ParseError {
     }
 }
 
-#[derive(Debug, PartialEq)]
-/// A message header, as described in the Language Server Protocol specification.
-enum LspHeader {
-    ContentType,
-    ContentLength(usize),
+/// Generates a Language Server Protocol compliant message.
+pub fn prepare_lsp_json(msg: &Value) -> Result<String, serde_json::error::Error> {
+    let request = serde_json::to_string(&msg)?;
+    Ok(format!(
+        "Content-Length: {}\r\n\r\n{}",
+        request.len(),
+        request
+    ))
 }
 
-/// Given a reference to a reader, attempts to read a Language Server Protocol message,
-/// blocking until a message is received.
-pub fn read_message<B: BufRead>(reader: &mut B) -> Result<Value, ParseError> {
-    let mut buffer = String::new();
-    let mut content_length: Option<usize> = None;
+const HEADER_CONTENT_LENGTH: &str = "content-length";
+const HEADER_CONTENT_TYPE: &str = "content-type";
 
-    // read in headers.
-    loop {
-        buffer.clear();
-        let num_bytes = reader.read_line(&mut buffer)?;
-        if num_bytes == 0 {
-            return Err(ParseError::Empty);
+/// The header part of a message.
+///
+/// Header names are case-insensitive, and whitespace around names and values is ignored.
+/// `Content-Type` is a media type with optional parameters, such as
+/// `application/vscode-jsonrpc; charset=utf-8`; a bare `utf-8` or `utf8` is also accepted,
+/// as older clients and servers send it. The only charset allowed is UTF-8. Headers other
+/// than `Content-Length` and `Content-Type` are kept as they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageHeaders {
+    headers: Vec<(String, String)>,
+    content_length: usize,
+    media_type: Option<String>,
+    charset: Option<String>,
+}
+
+impl MessageHeaders {
+    /// Reads the header part of a message, up to and including the empty line that ends it.
+    pub fn read<B: BufRead>(reader: &mut B) -> Result<Self, ParseError> {
+        let mut headers = MessageHeaders::default();
+        let mut content_length = None;
+        let mut buffer = String::new();
+        loop {
+            buffer.clear();
+            let num_bytes = reader.read_line(&mut buffer)?;
+            if num_bytes == 0 {
+                return Err(ParseError::Empty);
+            }
+            if buffer.trim().is_empty() {
+                // empty line is end of headers
+                break;
+            }
+            if let Some(len) = headers.parse_line(&buffer)? {
+                content_length = Some(len);
+            }
+        }
+        headers.content_length =
+            content_length.ok_or(format!("missing content-length header: {}", buffer))?;
+        Ok(headers)
+    }
+
+    /// The length of the content in bytes.
+    pub fn content_length(&self) -> usize {
+        self.content_length
+    }
+
+    /// The media type from `Content-Type`, in lowercase, if one was given.
+    pub fn media_type(&self) -> Option<&str> {
+        self.media_type.as_deref()
+    }
+
+    /// The charset from `Content-Type`, in lowercase, if one was given.
+    pub fn charset(&self) -> Option<&str> {
+        self.charset.as_deref()
+    }
+
+    /// The value of the first header called `name`, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every header, in the order they were sent.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Records a header line, returning the content length if that is what it gives.
+    fn parse_line(&mut self, line: &str) -> Result<Option<usize>, ParseError> {
+        let (name, value) = line
+            .split_once(':')
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .filter(|(name, _)| !name.is_empty() && !name.contains(char::is_whitespace))
+            .ok_or_else(|| ParseError::Unknown(format!("malformed header: {}", line)))?;
+        let mut content_length = None;
+        if name.eq_ignore_ascii_case(HEADER_CONTENT_LENGTH) {
+            content_length = Some(value.parse()?);
+        } else if name.eq_ignore_ascii_case(HEADER_CONTENT_TYPE) {
+            self.parse_content_type(value)?;
+        }
+        self.headers.push((name.to_owned(), value.to_owned()));
+        Ok(content_length)
+    }
+
+    fn parse_content_type(&mut self, value: &str) -> Result<(), ParseError> {
+        let mut parts = value.split(';');
+        let media_type = parts.next().unwrap_or_default().trim().to_lowercase();
+        let mut charset = None;
+        if media_type.contains('/') {
+            self.media_type = Some(media_type);
+        } else if !media_type.is_empty() {
+            // the bare charset that older versions of the protocol sent
+            charset = Some(media_type);
         }
-        // assert!(buffer.ends_with("\r\n"));
-        match &buffer {
-            s if s.trim().is_empty() => break, // empty line is end of headers
-            // s if s == "\r\n" => break, // empty line is end of headers
-            s => {
-                match parse_header(s)? {
-                    LspHeader::ContentLength(len) => content_length = Some(len),
-                    LspHeader::ContentType => (), // utf-8 only currently allowed value
-                };
+        for param in parts.filter(|param| !param.trim().is_empty()) {
+            let (name, value) = param
+                .split_once('=')
+                .ok_or_else(|| ParseError::Unknown(format!("malformed content type: {}", value)))?;
+            if name.trim().eq_ignore_ascii_case("charset") {
+                charset = Some(value.trim().trim_matches('"').to_lowercase());
+            }
+        }
+        if let Some(charset) = charset {
+            if charset != "utf-8" && charset != "utf8" {
+                return Err(ParseError::Encoding(format!(
+                    "Invalid encoding: {}",
+                    charset
+                )));
             }
-        };
+            self.charset = Some(charset);
+        }
+        Ok(())
     }
+}
 
-    let content_length =
-        content_length.ok_or(format!("missing content-length header: {}", buffer))?;
+/// Given a reference to a reader, attempts to read a Language Server Protocol message,
+/// blocking until a message is received.
+pub fn read_message<B: BufRead>(reader: &mut B) -> Result<Value, ParseError> {
+    read_message_with_headers(reader).map(|(_, message)| message)
+}
+
+/// Like [`read_message`], but also returns the message's headers.
+pub fn read_message_with_headers<B: BufRead>(
+    reader: &mut B,
+) -> Result<(MessageHeaders, Value), ParseError> {
+    let headers = MessageHeaders::read(reader)?;
     // message body isn't newline terminated, so we read content_length bytes
-    let mut body_buffer = vec![0; content_length];
+    let mut body_buffer = vec![0; headers.content_length()];
     reader.read_exact(&mut body_buffer)?;
     let body = String::from_utf8(body_buffer)?;
-    Ok(serde_json::from_str(&body)?)
+    Ok((headers, serde_json::from_str(&body)?))
 }
 
-const HEADER_CONTENT_LENGTH: &str = "content-length";
-const HEADER_CONTENT_TYPE: &str = "content-type";
+/// The asynchronous counterpart of [`read_message`], for readers driven by tokio.
+#[cfg(feature = "async")]
+pub async fn read_message_async<B>(reader: &mut B) -> Result<Value, ParseError>
+where
+    B: tokio::io::AsyncBufRead + Unpin,
+{
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt};
 
-/// Given a header string, attempts to extract and validate the name and value parts.
-fn parse_header(s: &str) -> Result<LspHeader, ParseError> {
-    let split: Vec<String> = s.split(": ").map(|s| s.trim().to_lowercase()).collect();
-    if split.len() != 2 {
-        return Err(ParseError::Unknown(format!("malformed header: {}", s)));
-    }
-    match split[0].as_ref() {
-        HEADER_CONTENT_TYPE => {
-            let encoding = split[1].to_lowercase();
-            let is_valid_encoding = ["utf-8", "utf8"]
-                .iter()
-                .any(|valid_encoding| *valid_encoding == encoding);
-            if is_valid_encoding {
-                Ok(LspHeader::ContentType)
-            } else {
-                Err(ParseError::Encoding(format!(
-                    "Invalid encoding: {}",
-                    split[1]
-                )))
-            }
+    // gather the header lines, to be parsed like those read synchronously
+    let mut header_block = String::new();
+    loop {
+        let line_start = header_block.len();
+        let num_bytes = reader.read_line(&mut header_block).await?;
+        if num_bytes == 0 {
+            return Err(ParseError::Empty);
+        }
+        if header_block[line_start..].trim().is_empty() {
+            break;
         }
-        HEADER_CONTENT_LENGTH => Ok(LspHeader::ContentLength(split[1].parse()?)),
-        _ => Err(ParseError::Unknown(format!("Unknown header: {}", s))),
     }
+    let headers = MessageHeaders::read(&mut header_block.as_bytes())?;
+
+    let mut body_buffer = vec![0; headers.content_length()];
+    reader.read_exact(&mut body_buffer).await?;
+    let body = String::from_utf8(body_buffer)?;
+    Ok(serde_json::from_str(&body)?)
 }
 
 #[cfg(test)]
@@ -158,45 +264,76 @@ mod tests {
     use super::*;
     use std::io::BufReader;
 
+    /// Parses a single header line on its own.
+    fn parse_header(line: &str) -> Result<(MessageHeaders, Option<usize>), ParseError> {
+        let mut headers = MessageHeaders::default();
+        let content_length = headers.parse_line(line)?;
+        Ok((headers, content_length))
+    }
+
     #[test]
     fn test_parse_header_content_length() {
         let header = "Content-Length: 132";
-        assert_eq!(
-            parse_header(header).ok(),
-            Some(LspHeader::ContentLength(132))
-        );
+        assert_eq!(parse_header(header).unwrap().1, Some(132));
+        let header = "content-length:132\r\n";
+        assert_eq!(parse_header(header).unwrap().1, Some(132));
     }
 
     #[test]
     fn test_parse_header_content_type() {
         let header = "Content-Type: utf-8";
-        let parsed = parse_header(header);
-        assert_eq!(parsed.ok(), Some(LspHeader::ContentType));
+        let (parsed, _) = parse_header(header).unwrap();
+        assert_eq!(parsed.charset(), Some("utf-8"));
+        assert_eq!(parsed.media_type(), None);
 
         // For backwards compatibility; see
         // https://microsoft.github.io/language-server-protocol/specifications/specification-3-16/#contentPart
         let header = "Content-Type: utf8";
-        let parsed = parse_header(header);
-        assert_eq!(parsed.ok(), Some(LspHeader::ContentType));
+        let (parsed, _) = parse_header(header).unwrap();
+        assert_eq!(parsed.charset(), Some("utf8"));
+
+        let header = "Content-Type: application/vscode-jsonrpc; charset=utf-8";
+        let (parsed, _) = parse_header(header).unwrap();
+        assert_eq!(parsed.media_type(), Some("application/vscode-jsonrpc"));
+        assert_eq!(parsed.charset(), Some("utf-8"));
+
+        let header = "content-type:Application/VSCode-JSONRPC ;charset = \"UTF-8\" ";
+        let (parsed, _) = parse_header(header).unwrap();
+        assert_eq!(parsed.media_type(), Some("application/vscode-jsonrpc"));
+        assert_eq!(parsed.charset(), Some("utf-8"));
+        assert_eq!(
+            parsed.get("Content-Type"),
+            Some("Application/VSCode-JSONRPC ;charset = \"UTF-8\"")
+        );
     }
 
     #[test]
     fn test_parse_header_invalid_content_type() {
-        let header = "Content-Type: ascii";
-        let parsed = parse_header(header);
-        assert!(parsed.is_err());
+        for header in [
+            "Content-Type: ascii",
+            "Content-Type: application/vscode-jsonrpc; charset=latin1",
+        ] {
+            match parse_header(header).err().unwrap() {
+                ParseError::Encoding(_) => (),
+                default => panic!("incorrect ParseError variant: {:#?}", default),
+            }
+        }
     }
 
     #[test]
     fn test_parse_header_malformed() {
         let test_cases = [
             ("", "malformed header: "),
-            ("Content-Length:132", "malformed header: Content-Length:132"),
+            ("Content-Length 132", "malformed header: Content-Length 132"),
+            (
+                "Content Length: 132",
+                "malformed header: Content Length: 132",
+            ),
         ];
         for (header, err_msg) in test_cases {
             let parsed_header = parse_header(header);
-            assert_eq!(parsed_header.as_ref().ok(), None);
-            match parsed_header.as_ref().err().unwrap() {
+            assert!(parsed_header.is_err());
+            match parsed_header.err().unwrap() {
                 ParseError::Unknown(s) => {
                     assert_eq!(*s, err_msg.to_string())
                 }
@@ -208,25 +345,34 @@ mod tests {
     #[test]
     fn test_parse_header_unknown() {
         let header = "Hello: world";
-        let parsed_header = parse_header(header);
-        assert_eq!(parsed_header.as_ref().ok(), None);
-        match parsed_header.as_ref().err().unwrap() {
-            ParseError::Unknown(s) => assert_eq!(*s, "Unknown header: Hello: world".to_string()),
-            default => panic!("incorrect ParseError variant: {:#?}", default),
-        }
+        let (parsed, content_length) = parse_header(header).unwrap();
+        assert_eq!(content_length, None);
+        assert_eq!(parsed.get("hello"), Some("world"));
+        assert_eq!(parsed.iter().collect::<Vec<_>>(), [("Hello", "world")]);
     }
 
     #[test]
     fn test_parse_error() {
         let header = "Content-Length: 132 hi";
         let parsed_header = parse_header(header);
-        assert_eq!(parsed_header.as_ref().ok(), None);
-        match parsed_header.as_ref().err().unwrap() {
+        assert!(parsed_header.is_err());
+        match parsed_header.err().unwrap() {
             ParseError::ParseInt(s) => println!("{:#?}", s),
             default => panic!("incorrect ParseError variant: {:#?}", default),
         }
     }
 
+    #[test]
+    fn test_read_message_with_headers() {
+        let inp = "Content-Length: 17\r\nX-Trace: abc\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\n{\"name\": \"value\"}";
+        let mut reader = BufReader::new(inp.as_bytes());
+        let (headers, message) = read_message_with_headers(&mut reader).unwrap();
+        assert_eq!(message, json!({"name": "value"}));
+        assert_eq!(headers.content_length(), 17);
+        assert_eq!(headers.get("x-trace"), Some("abc"));
+        assert_eq!(headers.iter().count(), 3);
+    }
+
     #[test]
     fn test_read_message() {
         let inps = vec![
@@ -247,6 +393,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::let_unit_value)]
     fn test_read_message_invalid_content_type() {
         let test_cases = [
             (
@@ -274,6 +421,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::let_unit_value)]
     fn test_read_message_missing_content_length() {
         let test_cases = [
             // Without the \n\r\n\r this leads to a failed header parse.
@@ -0,0 +1,205 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! Converting between LSP positions and offsets into text.
+//!
+//! The `character` of a [`Position`] counts code units of the negotiated
+//! [`PositionEncoding`], which is UTF-16 unless the server chose otherwise. Byte offsets are
+//! UTF-8 code units, and chars are UTF-32 code units, so converting between any of them is a
+//! matter of picking the encoding.
+
+use lsp_types::{Position, PositionEncodingKind};
+
+/// The unit in which the `character` of a position is counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PositionEncoding {
+    /// Bytes.
+    Utf8,
+    /// UTF-16 code units, the protocol's default.
+    #[default]
+    Utf16,
+    /// Chars.
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// The encoding named by `kind`, if it is one of the three the protocol defines.
+    pub fn from_kind(kind: &PositionEncodingKind) -> Option<Self> {
+        match kind.as_str() {
+            "utf-8" => Some(PositionEncoding::Utf8),
+            "utf-16" => Some(PositionEncoding::Utf16),
+            "utf-32" => Some(PositionEncoding::Utf32),
+            _ => None,
+        }
+    }
+
+    pub fn to_kind(self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// The number of code units needed to encode `c`.
+    pub fn char_len(self, c: char) -> usize {
+        match self {
+            PositionEncoding::Utf8 => c.len_utf8(),
+            PositionEncoding::Utf16 => c.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+
+    /// The number of code units needed to encode `text`.
+    pub fn len(self, text: &str) -> usize {
+        match self {
+            PositionEncoding::Utf8 => text.len(),
+            _ => text.chars().map(|c| self.char_len(c)).sum(),
+        }
+    }
+}
+
+/// The start of every line in a text, for converting between positions and byte offsets.
+///
+/// Lines end with `\n`, `\r\n` or `\r`, as in the protocol. Positions past the end of a line
+/// refer to the end of that line, and lines past the end of the text to the end of the text;
+/// positions inside a character refer to its start.
+#[derive(Debug, Clone)]
+pub struct LineIndex<'a> {
+    text: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let bytes = text.as_bytes();
+        let mut line_starts = vec![0];
+        for (i, &b) in bytes.iter().enumerate() {
+            let line_end = match b {
+                b'\n' => true,
+                b'\r' => bytes.get(i + 1) != Some(&b'\n'),
+                _ => false,
+            };
+            if line_end {
+                line_starts.push(i + 1);
+            }
+        }
+        LineIndex { text, line_starts }
+    }
+
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The text of line `line`, without its line ending.
+    pub fn line(&self, line: u32) -> Option<&'a str> {
+        let start = *self.line_starts.get(line as usize)?;
+        let end = self
+            .line_starts
+            .get(line as usize + 1)
+            .copied()
+            .unwrap_or(self.text.len());
+        let text = &self.text[start..end];
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        Some(text.strip_suffix('\r').unwrap_or(text))
+    }
+
+    /// The byte offset of `position`.
+    pub fn offset(&self, position: Position, encoding: PositionEncoding) -> usize {
+        let line = match self.line(position.line) {
+            Some(line) => line,
+            None => return self.text.len(),
+        };
+        let line_start = self.line_starts[position.line as usize];
+        let mut units = 0;
+        for (offset, c) in line.char_indices() {
+            units += encoding.char_len(c);
+            if units > position.character as usize {
+                return line_start + offset;
+            }
+        }
+        line_start + line.len()
+    }
+
+    /// The position of the byte at `offset`.
+    pub fn position(&self, offset: usize, encoding: PositionEncoding) -> Position {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let line_text = self.line(line as u32).unwrap();
+        let start = self.line_starts[line];
+        let column = (offset - start).min(line_text.len());
+        Position::new(line as u32, encoding.len(&line_text[..column]) as u32)
+    }
+
+    /// Converts a position from one encoding to another.
+    pub fn convert(
+        &self,
+        position: Position,
+        from: PositionEncoding,
+        to: PositionEncoding,
+    ) -> Position {
+        self.position(self.offset(position, from), to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_index_conversions() {
+        use PositionEncoding::*;
+
+        let text = "a€𝄞b\r\nxy\rz\n";
+        let index = LineIndex::new(text);
+        assert_eq!(index.line_count(), 4);
+        assert_eq!(index.line(0), Some("a€𝄞b"));
+        assert_eq!(index.line(2), Some("z"));
+        assert_eq!(index.line(3), Some(""));
+
+        // the same point, just before 'b'
+        assert_eq!(index.offset(Position::new(0, 8), Utf8), 8);
+        assert_eq!(index.offset(Position::new(0, 4), Utf16), 8);
+        assert_eq!(index.offset(Position::new(0, 3), Utf32), 8);
+        assert_eq!(index.position(8, Utf16), Position::new(0, 4));
+        assert_eq!(
+            index.convert(Position::new(0, 3), Utf32, Utf16),
+            Position::new(0, 4)
+        );
+
+        // inside '𝄞', past the end of a line, and past the end of the text
+        assert_eq!(index.offset(Position::new(0, 3), Utf16), 4);
+        assert_eq!(index.position(6, Utf8), Position::new(0, 4));
+        assert_eq!(index.offset(Position::new(0, 20), Utf16), 9);
+        assert_eq!(index.position(10, Utf16), Position::new(0, 5));
+        assert_eq!(index.offset(Position::new(2, 0), Utf16), 14);
+        assert_eq!(index.offset(Position::new(9, 0), Utf16), text.len());
+        assert_eq!(index.position(99, Utf16), Position::new(3, 0));
+    }
+}
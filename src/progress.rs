@@ -0,0 +1,321 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! Following the work done progress that a server reports with `$/progress`.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use lsp_types::request::WorkDoneProgressCreate;
+use lsp_types::{ProgressParams, ProgressParamsValue, ProgressToken, WorkDoneProgress};
+
+use crate::client::{LanguageServerRef, Subscription};
+
+/// The state of one piece of work in progress, as last reported by the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// The title from `begin`, or `None` if the token was created but the work has not begun.
+    pub title: Option<String>,
+    pub message: Option<String>,
+    pub percentage: Option<u32>,
+    pub cancellable: bool,
+}
+
+struct Shared {
+    active: Mutex<HashMap<ProgressToken, Progress>>,
+    quiescent: Condvar,
+    #[cfg(feature = "async")]
+    active_count: tokio::sync::watch::Sender<usize>,
+}
+
+impl Shared {
+    fn update(&self, token: ProgressToken, progress: WorkDoneProgress) {
+        let mut active = self.active.lock().unwrap();
+        match progress {
+            WorkDoneProgress::Begin(begin) => {
+                active.insert(
+                    token,
+                    Progress {
+                        title: Some(begin.title),
+                        message: begin.message,
+                        percentage: begin.percentage,
+                        cancellable: begin.cancellable.unwrap_or(false),
+                    },
+                );
+            }
+            WorkDoneProgress::Report(report) => {
+                // a report for a token that was never created, or has ended, is stale
+                if let Some(entry) = active.get_mut(&token) {
+                    if report.message.is_some() {
+                        entry.message = report.message;
+                    }
+                    if report.percentage.is_some() {
+                        entry.percentage = report.percentage;
+                    }
+                    if let Some(cancellable) = report.cancellable {
+                        entry.cancellable = cancellable;
+                    }
+                }
+            }
+            WorkDoneProgress::End(_) => {
+                active.remove(&token);
+            }
+        }
+        self.changed(&active);
+    }
+
+    fn create(&self, token: ProgressToken) {
+        let mut active = self.active.lock().unwrap();
+        active.entry(token).or_default();
+        self.changed(&active);
+    }
+
+    /// Ends the work of every token.
+    fn clear(&self) {
+        let mut active = self.active.lock().unwrap();
+        active.clear();
+        self.changed(&active);
+    }
+
+    fn changed(&self, active: &HashMap<ProgressToken, Progress>) {
+        if active.is_empty() {
+            self.quiescent.notify_all();
+        }
+        #[cfg(feature = "async")]
+        self.active_count.send_replace(active.len());
+    }
+}
+
+/// Held by the `$/progress` subscriber, which the server drops when it disconnects, to end
+/// the work that it will no longer report on.
+struct ClearOnDrop(Weak<Shared>);
+
+impl Drop for ClearOnDrop {
+    fn drop(&mut self) {
+        if let Some(shared) = self.0.upgrade() {
+            shared.clear();
+        }
+    }
+}
+
+/// Tracks the work done progress of a server.
+///
+/// The tracker answers `window/workDoneProgress/create`, and follows the `begin`, `report`
+/// and `end` notifications for each token. A token counts as active from the moment it is
+/// created or begun until its work ends. Progress for tokens that carry partial results
+/// rather than work done progress is ignored. Once the server disconnects, no token is
+/// active.
+#[derive(Clone)]
+pub struct ProgressTracker {
+    shared: Arc<Shared>,
+    _subscription: Arc<Subscription>,
+}
+
+impl ProgressTracker {
+    pub fn new<W: 'static + Write + Send>(server: &LanguageServerRef<W>) -> Self {
+        let shared = Arc::new(Shared {
+            active: Mutex::new(HashMap::new()),
+            quiescent: Condvar::new(),
+            #[cfg(feature = "async")]
+            active_count: tokio::sync::watch::Sender::new(0),
+        });
+        let weak = Arc::downgrade(&shared);
+        server.on_typed_request::<WorkDoneProgressCreate, _>(move |params| {
+            if let Some(shared) = weak.upgrade() {
+                shared.create(params.token);
+            }
+            Ok(())
+        });
+        let weak = Arc::downgrade(&shared);
+        let guard = ClearOnDrop(weak.clone());
+        let subscription = server.subscribe("$/progress", move |notification| {
+            // moves the guard into the subscriber, to be dropped with it
+            let _ = &guard;
+            let params = match serde_json::from_value::<ProgressParams>(notification.params) {
+                Ok(params) => params,
+                Err(_) => return,
+            };
+            let ProgressParamsValue::WorkDone(progress) = params.value;
+            if let Some(shared) = weak.upgrade() {
+                shared.update(params.token, progress);
+            }
+        });
+        ProgressTracker {
+            shared,
+            _subscription: Arc::new(subscription),
+        }
+    }
+
+    /// The progress of each active token.
+    pub fn active(&self) -> Vec<(ProgressToken, Progress)> {
+        let active = self.shared.active.lock().unwrap();
+        active
+            .iter()
+            .map(|(token, progress)| (token.clone(), progress.clone()))
+            .collect()
+    }
+
+    /// The progress of `token`, if it is active.
+    pub fn get(&self, token: &ProgressToken) -> Option<Progress> {
+        self.shared.active.lock().unwrap().get(token).cloned()
+    }
+
+    pub fn is_quiescent(&self) -> bool {
+        self.shared.active.lock().unwrap().is_empty()
+    }
+
+    /// Blocks until no token is active, or until `timeout` has passed. Returns whether the
+    /// server became quiescent.
+    ///
+    /// A server that has not begun any work yet is already quiescent, so waiting right after
+    /// `initialized` may return before the server starts indexing.
+    pub fn wait_until_quiescent(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut active = self.shared.active.lock().unwrap();
+        while !active.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            active = self
+                .shared
+                .quiescent
+                .wait_timeout(active, deadline - now)
+                .unwrap()
+                .0;
+        }
+        true
+    }
+
+    /// Waits until no token is active. Wrap it in a timeout to give up waiting.
+    ///
+    /// See [`wait_until_quiescent`](Self::wait_until_quiescent).
+    #[cfg(feature = "async")]
+    pub async fn quiescent(&self) {
+        let mut active_count = self.shared.active_count.subscribe();
+        // the sender lives as long as `self`, so this cannot fail
+        let _ = active_count.wait_for(|count| *count == 0).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::SharedBuffer;
+    use serde_json::json;
+    use std::thread;
+
+    #[test]
+    fn test_progress_tracking() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        let tracker = ProgressTracker::new(&lang_server);
+        let progress = |token: &str, value: serde_json::Value| {
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "$/progress",
+                "params": {"token": token, "value": value},
+            });
+            lang_server.handle_msg(&notification).unwrap();
+        };
+        let token = ProgressToken::String("indexing".into());
+        assert!(tracker.wait_until_quiescent(Duration::from_millis(0)));
+
+        let create = json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": "window/workDoneProgress/create",
+            "params": {"token": "indexing"},
+        });
+        lang_server.handle_msg(&create).unwrap();
+        let response = buffer.messages().pop().unwrap();
+        assert_eq!(response["id"], 7);
+        assert!(response["result"].is_null());
+        assert_eq!(tracker.get(&token), Some(Progress::default()));
+
+        progress(
+            "indexing",
+            json!({"kind": "begin", "title": "Indexing", "percentage": 0}),
+        );
+        progress("indexing", json!({"kind": "report", "message": "3/10"}));
+        // partial results for another request are not work done progress
+        progress("other", json!({"items": []}));
+        assert_eq!(
+            tracker.active(),
+            [(
+                token.clone(),
+                Progress {
+                    title: Some("Indexing".into()),
+                    message: Some("3/10".into()),
+                    percentage: Some(0),
+                    cancellable: false,
+                }
+            )]
+        );
+        assert!(!tracker.wait_until_quiescent(Duration::from_millis(10)));
+
+        let ender = {
+            let lang_server = lang_server.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                let end = json!({
+                    "jsonrpc": "2.0",
+                    "method": "$/progress",
+                    "params": {"token": "indexing", "value": {"kind": "end"}},
+                });
+                lang_server.handle_msg(&end).unwrap();
+            })
+        };
+        assert!(tracker.wait_until_quiescent(Duration::from_secs(5)));
+        assert!(tracker.is_quiescent());
+        ender.join().unwrap();
+
+        // a late report does not bring the ended work back
+        progress("indexing", json!({"kind": "report", "message": "10/10"}));
+        progress("stray", json!({"kind": "report", "percentage": 50}));
+        assert!(tracker.is_quiescent());
+        assert!(tracker.wait_until_quiescent(Duration::from_millis(0)));
+    }
+
+    #[test]
+    fn test_disconnect_ends_progress() {
+        let lang_server = LanguageServerRef::new(SharedBuffer::default(), None);
+        let tracker = ProgressTracker::new(&lang_server);
+        let begin = json!({
+            "jsonrpc": "2.0",
+            "method": "$/progress",
+            "params": {"token": 1, "value": {"kind": "begin", "title": "Indexing"}},
+        });
+        lang_server.handle_msg(&begin).unwrap();
+        assert!(!tracker.is_quiescent());
+
+        let disconnect = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            lang_server.handle_disconnect();
+        });
+        assert!(tracker.wait_until_quiescent(Duration::from_secs(5)));
+        assert!(tracker.active().is_empty());
+        disconnect.join().unwrap();
+    }
+}
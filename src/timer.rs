@@ -0,0 +1,139 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! A single background thread that runs jobs once their deadline has passed.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Entry {
+    deadline: Instant,
+    /// Breaks ties between equal deadlines, so jobs run in the order they were scheduled.
+    seq: u64,
+    job: Job,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    // reversed, so that the BinaryHeap yields the earliest deadline first
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
+}
+
+fn timer_thread() -> &'static Mutex<Sender<(Instant, Job)>> {
+    static TIMER: OnceLock<Mutex<Sender<(Instant, Job)>>> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel::<(Instant, Job)>();
+        thread::spawn(move || {
+            let mut queue = BinaryHeap::new();
+            let mut seq = 0;
+            loop {
+                let now = Instant::now();
+                while queue
+                    .peek()
+                    .is_some_and(|entry: &Entry| entry.deadline <= now)
+                {
+                    let job = queue.pop().unwrap().job;
+                    // a panicking job must not take every later timeout down with the thread
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                }
+                let received = match queue.peek() {
+                    Some(entry) => rx.recv_timeout(entry.deadline - now),
+                    None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok((deadline, job)) => {
+                        queue.push(Entry { deadline, seq, job });
+                        seq += 1;
+                    }
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        });
+        Mutex::new(tx)
+    })
+}
+
+/// Runs `job` on the timer thread once `delay` has elapsed.
+///
+/// Jobs share a single thread, so they should be quick. A job that panics does not stop
+/// later ones from running.
+pub(crate) fn schedule<F>(delay: Duration, job: F)
+where
+    F: 'static + Send + FnOnce(),
+{
+    let deadline = Instant::now() + delay;
+    let _ = timer_thread()
+        .lock()
+        .unwrap()
+        .send((deadline, Box::new(job)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jobs_run_in_deadline_order() {
+        let (tx, rx) = mpsc::channel();
+        for (delay, name) in [(30, "c"), (10, "a"), (20, "b"), (20, "b2")] {
+            let tx = tx.clone();
+            schedule(Duration::from_millis(delay), move || {
+                let _ = tx.send(name);
+            });
+        }
+        let order: Vec<_> = rx.iter().take(4).collect();
+        assert_eq!(order, ["a", "b", "b2", "c"]);
+    }
+
+    #[test]
+    fn test_panicking_job_does_not_stop_the_timer() {
+        schedule(Duration::from_millis(0), || panic!("job failed"));
+        let (tx, rx) = mpsc::channel();
+        schedule(Duration::from_millis(10), move || {
+            let _ = tx.send(());
+        });
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+}
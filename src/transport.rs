@@ -0,0 +1,827 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! The connections a client can talk to a server over.
+//!
+//! A [`Transport`] is anything that can be split into a reader for the server's messages
+//! and a writer for the client's. [`connect`] starts a client over any of them, reading on
+//! a background thread just as [`start_language_server`](crate::start_language_server)
+//! does for a child's stdio.
+
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+#[cfg(unix)]
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::{spawn_reader, LanguageServerRef};
+
+/// The halves of a transport, along with the server process at the other end, if the
+/// transport owns it.
+pub struct Parts<R, W> {
+    pub reader: R,
+    pub writer: W,
+    pub process: Option<Child>,
+}
+
+/// A two-way connection to a language server.
+pub trait Transport {
+    type Reader: 'static + Read + Send;
+    type Writer: 'static + Write + Send;
+
+    fn into_parts(self) -> io::Result<Parts<Self::Reader, Self::Writer>>;
+}
+
+/// Starts a client that talks to a server over `transport`.
+pub fn connect<T: Transport>(transport: T) -> io::Result<LanguageServerRef<T::Writer>> {
+    transport.into_parts().map(start)
+}
+
+fn start<R, W>(parts: Parts<R, W>) -> LanguageServerRef<W>
+where
+    R: 'static + Read + Send,
+    W: 'static + Write + Send,
+{
+    let lang_server = LanguageServerRef::new(parts.writer, parts.process);
+    spawn_reader(&lang_server, parts.reader);
+    lang_server
+}
+
+/// How long to keep trying to connect to a server that is not listening yet, and how long to
+/// wait between attempts. The wait doubles after each attempt, up to `max_delay`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    timeout: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Gives up once this long has passed since the first attempt.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Calls `attempt` until it succeeds, fails with an error that means something other
+    /// than that the server is not listening yet, or the timeout passes.
+    pub(crate) fn retry<T, F>(&self, mut attempt: F) -> io::Result<T>
+    where
+        F: FnMut() -> io::Result<T>,
+    {
+        let deadline = Instant::now() + self.timeout;
+        let mut delay = self.initial_delay;
+        loop {
+            let err = match attempt() {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let not_listening = matches!(
+                err.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::NotFound
+                    | io::ErrorKind::TimedOut
+            );
+            let now = Instant::now();
+            if !not_listening || now >= deadline {
+                return Err(err);
+            }
+            thread::sleep(delay.min(deadline - now));
+            delay = (delay * 2).min(self.max_delay);
+        }
+    }
+}
+
+/// Connects to a server listening on `addr`, retrying according to `backoff` until it is.
+pub fn connect_tcp<A: ToSocketAddrs>(
+    addr: A,
+    backoff: Backoff,
+) -> io::Result<LanguageServerRef<TcpStream>> {
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    let stream = backoff.retry(|| TcpStream::connect(&addrs[..]))?;
+    // messages are written whole, and should not wait for more to follow
+    stream.set_nodelay(true)?;
+    connect(stream)
+}
+
+/// Connects to a server listening on the Unix domain socket at `path`, retrying according to
+/// `backoff` until it is.
+#[cfg(unix)]
+pub fn connect_unix<P: AsRef<Path>>(
+    path: P,
+    backoff: Backoff,
+) -> io::Result<LanguageServerRef<UnixStream>> {
+    let stream = backoff.retry(|| UnixStream::connect(path.as_ref()))?;
+    connect(stream)
+}
+
+/// Connects to a server over a pair of named pipes, reading its messages from `input` and
+/// writing to `output`.
+///
+/// Opening a pipe blocks until the server opens the other end, so this blocks until the
+/// server has opened both. They are opened at once, whichever order the server opens them in.
+#[cfg(unix)]
+pub fn connect_fifos<P, Q>(input: P, output: Q) -> io::Result<LanguageServerRef<File>>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let input = input.as_ref().to_owned();
+    let reader = {
+        let input = input.clone();
+        thread::spawn(move || File::open(input))
+    };
+    let writer = match OpenOptions::new().write(true).open(output) {
+        Ok(writer) => writer,
+        Err(err) => {
+            // the server will never come, so open the other end of the input pipe ourselves
+            // to let the thread waiting on it finish
+            let _ = OpenOptions::new().write(true).open(&input);
+            let _ = reader.join();
+            return Err(err);
+        }
+    };
+    let reader = reader
+        .join()
+        .map_err(|_| io::Error::other("opening the input pipe panicked"))??;
+    connect((reader, writer))
+}
+
+/// The stdin and stdout of a spawned server, which must both be piped. If they are not, the
+/// server is killed, as nothing could talk to it.
+impl Transport for Child {
+    type Reader = ChildStdout;
+    type Writer = ChildStdin;
+
+    fn into_parts(mut self) -> io::Result<Parts<ChildStdout, ChildStdin>> {
+        let (writer, reader) = match (self.stdin.take(), self.stdout.take()) {
+            (Some(writer), Some(reader)) => (writer, reader),
+            _ => {
+                let _ = self.kill();
+                let _ = self.wait();
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "stdio is not piped",
+                ));
+            }
+        };
+        Ok(Parts {
+            reader,
+            writer,
+            process: Some(self),
+        })
+    }
+}
+
+impl Transport for TcpStream {
+    type Reader = TcpStream;
+    type Writer = TcpStream;
+
+    fn into_parts(self) -> io::Result<Parts<TcpStream, TcpStream>> {
+        Ok(Parts {
+            reader: self.try_clone()?,
+            writer: self,
+            process: None,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    type Reader = UnixStream;
+    type Writer = UnixStream;
+
+    fn into_parts(self) -> io::Result<Parts<UnixStream, UnixStream>> {
+        Ok(Parts {
+            reader: self.try_clone()?,
+            writer: self,
+            process: None,
+        })
+    }
+}
+
+/// A socket of either kind.
+#[derive(Debug)]
+pub enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Socket {
+    pub fn try_clone(&self) -> io::Result<Socket> {
+        match self {
+            Socket::Tcp(stream) => stream.try_clone().map(Socket::Tcp),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.try_clone().map(Socket::Unix),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Socket::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Transport for Socket {
+    type Reader = Socket;
+    type Writer = Socket;
+
+    fn into_parts(self) -> io::Result<Parts<Socket, Socket>> {
+        Ok(Parts {
+            reader: self.try_clone()?,
+            writer: self,
+            process: None,
+        })
+    }
+}
+
+/// Where to listen for a server to connect back to the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    /// A TCP address. Port 0 listens on any free port.
+    Tcp(SocketAddr),
+    /// The path of a Unix domain socket, which must not exist yet.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    fn bind(address: &ListenAddress) -> io::Result<Listener> {
+        let listener = match address {
+            ListenAddress::Tcp(addr) => Listener::Tcp(TcpListener::bind(addr)?),
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => Listener::Unix(UnixListener::bind(path)?, path.clone()),
+        };
+        match &listener {
+            Listener::Tcp(listener) => listener.set_nonblocking(true)?,
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.set_nonblocking(true)?,
+        }
+        Ok(listener)
+    }
+
+    /// Replaces the placeholders in `arg` with the address being listened on.
+    fn substitute(&self, arg: &str) -> io::Result<String> {
+        Ok(match self {
+            Listener::Tcp(listener) => {
+                let addr = listener.local_addr()?;
+                arg.replace("{address}", &addr.to_string())
+                    .replace("{host}", &addr.ip().to_string())
+                    .replace("{port}", &addr.port().to_string())
+            }
+            #[cfg(unix)]
+            Listener::Unix(_, path) => {
+                let path = path.to_string_lossy();
+                arg.replace("{address}", &path).replace("{path}", &path)
+            }
+        })
+    }
+
+    fn accept(&self) -> io::Result<Socket> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                stream.set_nodelay(true)?;
+                Ok(Socket::Tcp(stream))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nonblocking(false)?;
+                Ok(Socket::Unix(stream))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Listens on `address`, spawns a server that connects back to it, and talks to the server
+/// over the first connection it makes.
+///
+/// In `args`, `{address}` is replaced with the address being listened on: `host:port` for
+/// TCP, or the path of a Unix socket. `{host}` and `{port}`, or `{path}`, are replaced with
+/// its parts. If the server does not connect within `timeout` it is killed, and the error's
+/// kind is [`TimedOut`](io::ErrorKind::TimedOut).
+///
+/// The server's stdin and stdout are closed, so that anything it prints does not mix with the
+/// client's output. Its stderr is inherited.
+pub fn spawn_reverse<P, S>(
+    program: P,
+    args: &[S],
+    address: &ListenAddress,
+    timeout: Duration,
+) -> io::Result<LanguageServerRef<Socket>>
+where
+    P: AsRef<OsStr>,
+    S: AsRef<str>,
+{
+    let listener = Listener::bind(address)?;
+    let args = args
+        .iter()
+        .map(|arg| listener.substitute(arg.as_ref()))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    let deadline = Instant::now() + timeout;
+    let socket = loop {
+        let err = match listener.accept() {
+            Ok(socket) => break socket,
+            Err(err) => err,
+        };
+        let failure = if err.kind() != io::ErrorKind::WouldBlock {
+            err
+        } else if let Some(status) = child.try_wait()? {
+            io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("server exited with {} before connecting", status),
+            )
+        } else if Instant::now() >= deadline {
+            io::Error::new(io::ErrorKind::TimedOut, "server did not connect in time")
+        } else {
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        };
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(failure);
+    };
+    drop(listener);
+
+    let mut parts = socket.into_parts()?;
+    parts.process = Some(child);
+    Ok(start(parts))
+}
+
+/// Any reader paired with any writer.
+impl<R, W> Transport for (R, W)
+where
+    R: 'static + Read + Send,
+    W: 'static + Write + Send,
+{
+    type Reader = R;
+    type Writer = W;
+
+    fn into_parts(self) -> io::Result<Parts<R, W>> {
+        Ok(Parts {
+            reader: self.0,
+            writer: self.1,
+            process: None,
+        })
+    }
+}
+
+#[derive(Default)]
+struct PipeBuffer {
+    bytes: VecDeque<u8>,
+    reader_closed: bool,
+    writer_closed: bool,
+}
+
+#[derive(Default)]
+struct PipeShared {
+    buffer: Mutex<PipeBuffer>,
+    readable: Condvar,
+}
+
+/// The reading end of an in-memory [`pipe`], which reaches the end of its input once the
+/// writing end is dropped.
+pub struct PipeReader(Arc<PipeShared>);
+
+/// The writing end of an in-memory [`pipe`]. Writes fail once the reading end is dropped.
+pub struct PipeWriter(Arc<PipeShared>);
+
+/// Creates a one-way, in-memory pipe.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let shared = Arc::new(PipeShared::default());
+    (PipeReader(shared.clone()), PipeWriter(shared))
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffer = self.0.buffer.lock().unwrap();
+        while buffer.bytes.is_empty() && !buffer.writer_closed {
+            buffer = self.0.readable.wait(buffer).unwrap();
+        }
+        let len = buf.len().min(buffer.bytes.len());
+        for (dest, byte) in buf.iter_mut().zip(buffer.bytes.drain(..len)) {
+            *dest = byte;
+        }
+        Ok(len)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut buffer = self.0.buffer.lock().unwrap();
+        buffer.reader_closed = true;
+        buffer.bytes.clear();
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut buffer = self.0.buffer.lock().unwrap();
+        if buffer.reader_closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the reading end of the pipe has been dropped",
+            ));
+        }
+        buffer.bytes.extend(buf);
+        self.0.readable.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.buffer.lock().unwrap().writer_closed = true;
+        self.0.readable.notify_all();
+    }
+}
+
+/// One end of an in-memory [`duplex`] connection, which reads what the other end writes.
+pub struct Duplex {
+    reader: PipeReader,
+    writer: PipeWriter,
+}
+
+/// Creates a pair of connected, in-memory ends, for running a server in the same process
+/// as the client, or for testing.
+pub fn duplex() -> (Duplex, Duplex) {
+    let (client_reader, server_writer) = pipe();
+    let (server_reader, client_writer) = pipe();
+    (
+        Duplex {
+            reader: client_reader,
+            writer: client_writer,
+        },
+        Duplex {
+            reader: server_reader,
+            writer: server_writer,
+        },
+    )
+}
+
+impl Read for Duplex {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for Duplex {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Transport for Duplex {
+    type Reader = PipeReader;
+    type Writer = PipeWriter;
+
+    fn into_parts(self) -> io::Result<Parts<PipeReader, PipeWriter>> {
+        Ok(Parts {
+            reader: self.reader,
+            writer: self.writer,
+            process: None,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::BufReader;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    use serde_json::value::Value;
+
+    use super::*;
+    use crate::error::ResponseError;
+    use crate::parsing::{self, prepare_lsp_json};
+
+    /// Answers each request with its own params, until the client disconnects or sends
+    /// `exit`.
+    fn echo_until_exit<R: Read, W: Write>(reader: R, mut writer: W) {
+        let mut reader = BufReader::new(reader);
+        while let Ok(message) = parsing::read_message(&mut reader) {
+            if message["method"] == "exit" {
+                break;
+            }
+            let response = json!({
+                "jsonrpc": "2.0",
+                "id": message["id"],
+                "result": message["params"],
+            });
+            let response = prepare_lsp_json(&response).unwrap();
+            if writer.write_all(response.as_bytes()).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Runs [`echo_until_exit`] on a new thread.
+    pub(crate) fn serve_echo<R, W>(reader: R, writer: W)
+    where
+        R: 'static + Read + Send,
+        W: 'static + Write + Send,
+    {
+        thread::spawn(move || echo_until_exit(reader, writer));
+    }
+
+    /// Stands in for a server that connects back: connects to `address` from a new thread
+    /// once something is listening there, and echoes requests until the client hangs up.
+    fn connect_back(address: ListenAddress) {
+        thread::spawn(move || match address {
+            ListenAddress::Tcp(addr) => {
+                let stream = Backoff::new().retry(|| TcpStream::connect(addr)).unwrap();
+                echo_until_exit(stream.try_clone().unwrap(), stream);
+            }
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                let stream = Backoff::new().retry(|| UnixStream::connect(&path)).unwrap();
+                echo_until_exit(stream.try_clone().unwrap(), stream);
+            }
+        });
+    }
+
+    /// Sends a request, returning its outcome.
+    pub(crate) fn echo<W: 'static + Write + Send>(
+        lang_server: &LanguageServerRef<W>,
+        params: Value,
+    ) -> Result<Value, Value> {
+        let (tx, rx) = mpsc::channel();
+        lang_server
+            .send_request("echo", &params, move |result| tx.send(result).unwrap())
+            .unwrap();
+        rx.recv()
+            .unwrap()
+            .map(|mut response| response["result"].take())
+    }
+
+    #[test]
+    fn test_duplex_transport() {
+        let (client, server) = duplex();
+        let lang_server = connect(client).unwrap();
+        serve_echo(server.reader, server.writer);
+        assert_eq!(echo(&lang_server, json!({"a": 1})), Ok(json!({"a": 1})));
+
+        // dropping the server's end disconnects the client
+        let (client, server) = duplex();
+        let lang_server = connect(client).unwrap();
+        let (tx, rx) = mpsc::channel();
+        lang_server
+            .send_request("initialize", &json!({}), move |result| {
+                tx.send(result).unwrap()
+            })
+            .unwrap();
+        drop(server);
+        let error = rx.recv().unwrap().unwrap_err();
+        assert_eq!(
+            ResponseError::from_error_response(&error),
+            ResponseError::server_disconnected()
+        );
+        assert!(lang_server.send_notification("exit", &json!(null)).is_err());
+    }
+
+    #[test]
+    fn test_connect_tcp_retries() {
+        // find a port that nothing is listening on yet
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let backoff = Backoff::new()
+            .initial_delay(Duration::from_millis(10))
+            .timeout(Duration::from_millis(50));
+        let err = connect_tcp(addr, backoff).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let listener = TcpListener::bind(addr).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            serve_echo(stream.try_clone().unwrap(), stream);
+        });
+        let lang_server = connect_tcp(addr, Backoff::new()).unwrap();
+        assert_eq!(echo(&lang_server, json!("hi")), Ok(json!("hi")));
+    }
+
+    #[test]
+    fn test_spawn_reverse_tcp() {
+        // find a port that nothing is listening on yet, for the server to connect back to
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let address = ListenAddress::Tcp(addr);
+        connect_back(address.clone());
+        let lang_server =
+            spawn_reverse("sleep", &["30"], &address, Duration::from_secs(10)).unwrap();
+        assert_eq!(echo(&lang_server, json!({"a": 1})), Ok(json!({"a": 1})));
+        lang_server.kill().unwrap();
+
+        let listener = Listener::bind(&address).unwrap();
+        let substituted = listener.substitute("{host}:{port} {address}").unwrap();
+        assert_eq!(substituted, format!("{} {}", addr, addr));
+        drop(listener);
+
+        let err = spawn_reverse(
+            "sh",
+            &["-c", "sleep 5"],
+            &address,
+            Duration::from_millis(50),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        let err = spawn_reverse("sh", &["-c", "exit 3"], &address, Duration::from_secs(5))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_spawn_reverse_unix() {
+        let path = std::env::temp_dir().join(format!("lsp-client-{}.sock", std::process::id()));
+        let address = ListenAddress::Unix(path.clone());
+        connect_back(address.clone());
+        let lang_server =
+            spawn_reverse("sleep", &["30"], &address, Duration::from_secs(10)).unwrap();
+        assert_eq!(echo(&lang_server, json!([1])), Ok(json!([1])));
+        assert!(!path.exists());
+        lang_server.kill().unwrap();
+
+        let listener = Listener::bind(&address).unwrap();
+        let substituted = listener.substitute("{path} {address}").unwrap();
+        assert_eq!(
+            substituted,
+            format!("{} {}", path.display(), path.display())
+        );
+        drop(listener);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_transports() {
+        let dir = std::env::temp_dir();
+        let name = |suffix: &str| dir.join(format!("lsp-client-{}-{}", std::process::id(), suffix));
+
+        let path = name("server.sock");
+        let _ = std::fs::remove_file(&path);
+        let listening = path.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            let (stream, _) = UnixListener::bind(listening).unwrap().accept().unwrap();
+            serve_echo(stream.try_clone().unwrap(), stream);
+        });
+        let lang_server = connect_unix(&path, Backoff::new()).unwrap();
+        assert_eq!(echo(&lang_server, json!(1)), Ok(json!(1)));
+        std::fs::remove_file(&path).unwrap();
+
+        let (to_client, to_server) = (name("to-client"), name("to-server"));
+        for fifo in [&to_client, &to_server] {
+            let _ = std::fs::remove_file(fifo);
+            let made = Command::new("mkfifo").arg(fifo).status().unwrap();
+            assert!(made.success());
+        }
+        let (input, output) = (to_server.clone(), to_client.clone());
+        thread::spawn(move || {
+            let reader = File::open(input).unwrap();
+            let writer = OpenOptions::new().write(true).open(output).unwrap();
+            serve_echo(reader, writer);
+        });
+        let lang_server = connect_fifos(&to_client, &to_server).unwrap();
+        assert_eq!(echo(&lang_server, json!("fifo")), Ok(json!("fifo")));
+
+        // failing to open the output pipe does not leave anything waiting on the input
+        let unused = name("unused");
+        let _ = std::fs::remove_file(&unused);
+        assert!(Command::new("mkfifo")
+            .arg(&unused)
+            .status()
+            .unwrap()
+            .success());
+        let err = connect_fifos(&unused, name("missing")).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        for fifo in [to_client, to_server, unused] {
+            std::fs::remove_file(fifo).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_tcp_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let lang_server = connect(stream).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        serve_echo(stream.try_clone().unwrap(), stream);
+        assert_eq!(echo(&lang_server, json!([1, 2])), Ok(json!([1, 2])));
+    }
+}
@@ -0,0 +1,290 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! Strongly typed requests and notifications, using the message definitions from
+//! [`lsp_types`].
+//!
+//! Each [`Request`] type ties a method name to its params and result types, so for instance
+//! `lang_server.request::<HoverRequest, _>(params, callback)` sends `textDocument/hover` and
+//! hands the callback an `Option<lsp_types::Hover>`. This sits on top of the untyped `Value` API, which
+//! remains available for anything not covered here.
+
+use std::fmt;
+use std::io::Write;
+
+use lsp_types::notification::Notification as LspNotification;
+use lsp_types::request::Request;
+use serde_json::value::Value;
+
+use crate::client::{LanguageServerRef, RequestHandle, Subscription};
+use crate::error::{ClientError, ResponseError};
+
+/// The ways a typed request can fail once it has been sent.
+#[derive(Debug)]
+pub enum TypedError {
+    /// The server responded with an error.
+    Response(ResponseError),
+    /// The server's result could not be deserialized into the request's result type.
+    Deserialize(serde_json::Error),
+}
+
+impl fmt::Display for TypedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypedError::Response(err) => write!(f, "server error: {}", err),
+            TypedError::Deserialize(err) => write!(f, "unexpected result: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TypedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TypedError::Response(err) => Some(err),
+            TypedError::Deserialize(err) => Some(err),
+        }
+    }
+}
+
+/// Converts the untyped outcome of a request into the result type of `R`.
+pub(crate) fn typed_result<R: Request>(
+    result: Result<Value, Value>,
+) -> Result<R::Result, TypedError> {
+    match result {
+        Ok(mut response) => {
+            serde_json::from_value(response["result"].take()).map_err(TypedError::Deserialize)
+        }
+        Err(response) => Err(TypedError::Response(ResponseError::from_error_response(
+            &response,
+        ))),
+    }
+}
+
+impl<W: 'static + Write + Send> LanguageServerRef<W> {
+    /// Sends the request `R`, passing its deserialized result to `completion`.
+    ///
+    /// Like [`send_request`](Self::send_request), this is subject to the default timeout.
+    pub fn request<R, CB>(
+        &self,
+        params: R::Params,
+        completion: CB,
+    ) -> Result<RequestHandle, ClientError>
+    where
+        R: Request,
+        CB: 'static + Send + FnOnce(Result<R::Result, TypedError>),
+    {
+        let params = serde_json::to_value(params)?;
+        self.send_request(R::METHOD, &params, move |result| {
+            completion(typed_result::<R>(result))
+        })
+    }
+
+    /// Sends the request `R` with a `partialResultToken`, passing the value of each partial
+    /// result to `partial_result` and the deserialized result to `completion`.
+    ///
+    /// See [`send_request_with_partial_results`](Self::send_request_with_partial_results).
+    pub fn request_with_partial_results<R, P, CB>(
+        &self,
+        params: R::Params,
+        partial_result: P,
+        completion: CB,
+    ) -> Result<RequestHandle, ClientError>
+    where
+        R: Request,
+        P: 'static + Send + Sync + Fn(Value),
+        CB: 'static + Send + FnOnce(Result<R::Result, TypedError>),
+    {
+        let params = serde_json::to_value(params)?;
+        self.send_request_with_partial_results(R::METHOD, &params, partial_result, move |result| {
+            completion(typed_result::<R>(result))
+        })
+    }
+
+    /// Sends the notification `N`.
+    pub fn notify<N: LspNotification>(&self, params: N::Params) -> Result<(), ClientError> {
+        let params = serde_json::to_value(params)?;
+        self.send_notification(N::METHOD, &params)
+    }
+
+    /// Registers `handler` to answer the server-to-client request `R`. Requests whose params
+    /// cannot be deserialized are answered with an `InvalidParams` error.
+    ///
+    /// See [`on_request`](Self::on_request).
+    pub fn on_typed_request<R, F>(&self, handler: F)
+    where
+        R: Request,
+        F: 'static + Send + Sync + Fn(R::Params) -> Result<R::Result, ResponseError>,
+    {
+        self.on_request(R::METHOD, move |params| {
+            let params = serde_json::from_value(params)
+                .map_err(|err| ResponseError::invalid_params(err.to_string()))?;
+            let result = handler(params)?;
+            serde_json::to_value(result)
+                .map_err(|err| ResponseError::internal_error(err.to_string()))
+        });
+    }
+
+    /// Calls `handler` with the params of every notification `N` sent by the server.
+    /// Notifications whose params cannot be deserialized are passed to the error sink.
+    ///
+    /// See [`subscribe`](Self::subscribe).
+    pub fn subscribe_typed<N, F>(&self, handler: F) -> Subscription
+    where
+        N: LspNotification,
+        F: 'static + Send + Sync + Fn(N::Params),
+    {
+        let lang_server = self.downgrade();
+        self.subscribe(N::METHOD, move |notification| match serde_json::from_value(
+            notification.params,
+        ) {
+            Ok(params) => handler(params),
+            Err(err) => {
+                if let Some(lang_server) = lang_server.upgrade() {
+                    lang_server.report_error(ClientError::Protocol(format!(
+                        "invalid params for {}: {}",
+                        N::METHOD,
+                        err
+                    )));
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use lsp_types::notification::LogMessage;
+    use lsp_types::request::{HoverRequest, Shutdown, WorkspaceConfiguration};
+    use lsp_types::{
+        HoverParams, MessageType, Position, TextDocumentIdentifier, TextDocumentPositionParams,
+        Url, WorkDoneProgressParams,
+    };
+
+    use super::*;
+    use crate::client::tests::SharedBuffer;
+
+    fn hover_params() -> HoverParams {
+        HoverParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: Url::parse("file:///a.rs").unwrap(),
+                },
+                position: Position::new(0, 0),
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        }
+    }
+
+    #[test]
+    fn test_typed_request_results() {
+        let lang_server = LanguageServerRef::new(Vec::new(), None);
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..3 {
+            let tx = tx.clone();
+            lang_server
+                .request::<HoverRequest, _>(hover_params(), move |result| {
+                    let _ = tx.send(result);
+                })
+                .unwrap();
+        }
+        let responses = [
+            json!({"jsonrpc": "2.0", "id": 1, "result": {"contents": "docs"}}),
+            json!({"jsonrpc": "2.0", "id": 2, "result": {"contents": 7}}),
+            json!({"jsonrpc": "2.0", "id": 3, "error": {"code": -32801, "message": "modified"}}),
+        ];
+        for response in responses {
+            lang_server.handle_msg(&response).unwrap();
+        }
+
+        let hover = rx.recv().unwrap().unwrap().unwrap();
+        assert_eq!(
+            hover.contents,
+            lsp_types::HoverContents::Scalar(lsp_types::MarkedString::String("docs".into()))
+        );
+        assert!(matches!(
+            rx.recv().unwrap(),
+            Err(TypedError::Deserialize(_))
+        ));
+        match rx.recv().unwrap() {
+            Err(TypedError::Response(err)) => assert_eq!(err.code, -32801),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let (tx, rx) = mpsc::channel();
+        lang_server
+            .request::<Shutdown, _>((), move |result| {
+                let _ = tx.send(result);
+            })
+            .unwrap();
+        lang_server
+            .handle_msg(&json!({"jsonrpc": "2.0", "id": 4, "result": null}))
+            .unwrap();
+        assert!(rx.recv().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_typed_handlers() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        lang_server.on_typed_request::<WorkspaceConfiguration, _>(|params| {
+            Ok(vec![Value::Null; params.items.len()])
+        });
+        let (tx, rx) = mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        let _subscription = lang_server.subscribe_typed::<LogMessage, _>(move |params| {
+            let _ = tx.lock().unwrap().send(params);
+        });
+
+        lang_server
+            .handle_msg(&json!({
+                "jsonrpc": "2.0",
+                "method": "window/logMessage",
+                "params": {"type": 2, "message": "careful"},
+            }))
+            .unwrap();
+        let params = rx.recv().unwrap();
+        assert_eq!(params.typ, MessageType::WARNING);
+        assert_eq!(params.message, "careful");
+
+        lang_server
+            .handle_msg(&json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "workspace/configuration",
+                "params": {"items": [{}]},
+            }))
+            .unwrap();
+        assert!(buffer.contents().ends_with(r#""result":[null]}"#));
+
+        lang_server
+            .handle_msg(&json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "workspace/configuration",
+                "params": {"items": 3},
+            }))
+            .unwrap();
+        assert!(buffer.contents().contains(r#""code":-32602"#));
+    }
+}
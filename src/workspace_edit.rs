@@ -0,0 +1,695 @@
+//MIT License
+
+//Copyright (c) 2017 Colin Rothfels
+
+//Permission is hereby granted, free of charge, to any person obtaining a copy
+//of this software and associated documentation files (the "Software"), to deal
+//in the Software without restriction, including without limitation the rights
+//to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+//copies of the Software, and to permit persons to whom the Software is
+//furnished to do so, subject to the following conditions:
+
+//The above copyright notice and this permission notice shall be included in all
+//copies or substantial portions of the Software.
+
+//THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+//IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+//FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+//AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+//LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+//OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+//SOFTWARE.
+
+//! Applying the `WorkspaceEdit`s that servers return for renames and code actions.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::{fmt, mem};
+
+use lsp_types::request::ApplyWorkspaceEdit;
+use lsp_types::{
+    ApplyWorkspaceEditResponse, DocumentChangeOperation, DocumentChanges, OneOf, ResourceOp,
+    TextDocumentEdit, TextEdit, Url, WorkspaceEdit,
+};
+
+use crate::documents::DocumentStore;
+use crate::error::{ClientError, ResponseError};
+use crate::position::{LineIndex, PositionEncoding};
+
+/// The ways applying a workspace edit can fail.
+#[derive(Debug)]
+pub enum EditError {
+    /// A text document edit was made against a different version of an open document.
+    VersionMismatch {
+        uri: Url,
+        expected: i32,
+        actual: i32,
+    },
+    /// Two edits to the document replace some of the same text.
+    OverlappingEdits(Url),
+    /// The URI does not name a local file.
+    NotAFile(Url),
+    /// A file to be created, or renamed to, already exists.
+    AlreadyExists(Url),
+    /// A file to be edited, renamed or deleted does not exist.
+    NotFound(Url),
+    Io(io::Error),
+    /// Updating an open document failed.
+    Client(ClientError),
+}
+
+impl From<io::Error> for EditError {
+    fn from(err: io::Error) -> EditError {
+        EditError::Io(err)
+    }
+}
+
+impl From<ClientError> for EditError {
+    fn from(err: ClientError) -> EditError {
+        EditError::Client(err)
+    }
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::VersionMismatch {
+                uri,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "edit to {} expects version {}, but the document is at version {}",
+                uri, expected, actual
+            ),
+            EditError::OverlappingEdits(uri) => write!(f, "edits to {} overlap", uri),
+            EditError::NotAFile(uri) => write!(f, "{} is not a local file", uri),
+            EditError::AlreadyExists(uri) => write!(f, "{} already exists", uri),
+            EditError::NotFound(uri) => write!(f, "{} does not exist", uri),
+            EditError::Io(err) => write!(f, "error accessing file: {}", err),
+            EditError::Client(err) => write!(f, "error updating document: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for EditError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EditError::Io(err) => Some(err),
+            EditError::Client(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Applies `edits` to `text`, with ranges in `encoding`. Every range refers to the original
+/// text; edits that insert at the same position are applied in order.
+///
+/// Returns `None` if any two edits overlap.
+pub fn apply_text_edits(
+    text: &str,
+    edits: &[TextEdit],
+    encoding: PositionEncoding,
+) -> Option<String> {
+    let index = LineIndex::new(text);
+    let mut ranges: Vec<_> = edits
+        .iter()
+        .map(|edit| {
+            let start = index.offset(edit.range.start, encoding);
+            let end = index.offset(edit.range.end, encoding).max(start);
+            (start, end, edit.new_text.as_str())
+        })
+        .collect();
+    // stable, so inserts at the same position keep their order
+    ranges.sort_by_key(|&(start, end, _)| (start, end));
+
+    let mut result = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (start, end, new_text) in ranges {
+        if start < copied {
+            return None;
+        }
+        result.push_str(&text[copied..start]);
+        result.push_str(new_text);
+        copied = end;
+    }
+    result.push_str(&text[copied..]);
+    Some(result)
+}
+
+fn file_path(uri: &Url) -> Result<PathBuf, EditError> {
+    uri.to_file_path()
+        .map_err(|()| EditError::NotAFile(uri.clone()))
+}
+
+/// What the edit has done to a file so far.
+#[derive(Clone)]
+enum Entry {
+    Missing,
+    /// A file or directory, as it is on disk.
+    Unchanged,
+    /// A file whose text is known.
+    Text(String),
+}
+
+/// A change to files on disk or to open documents, in the order the edit makes them.
+enum Step {
+    Create(Url),
+    Rename(Url, Url),
+    Delete { uri: Url, recursive: bool },
+}
+
+/// Works out the effect of an edit without touching anything, so that an edit that is bound
+/// to fail changes nothing.
+struct Plan<'a, W: Write> {
+    documents: &'a DocumentStore<W>,
+    encoding: PositionEncoding,
+    entries: HashMap<Url, Entry>,
+    steps: Vec<Step>,
+    /// Files whose text has been edited, at the URI they end up with.
+    edited: HashSet<Url>,
+}
+
+impl<'a, W: 'static + Write + Send> Plan<'a, W> {
+    fn entry(&mut self, uri: &Url) -> Result<&mut Entry, EditError> {
+        if !self.entries.contains_key(uri) {
+            let entry = match self.documents.get(uri) {
+                Some(document) => Entry::Text(document.text().to_owned()),
+                None if file_path(uri)?.exists() => Entry::Unchanged,
+                None => Entry::Missing,
+            };
+            self.entries.insert(uri.clone(), entry);
+        }
+        Ok(self.entries.get_mut(uri).unwrap())
+    }
+
+    /// The text of a file, reading it from disk if the edit has not touched it yet.
+    fn text(&mut self, uri: &Url) -> Result<&mut String, EditError> {
+        let entry = self.entry(uri)?;
+        match entry {
+            Entry::Missing => return Err(EditError::NotFound(uri.clone())),
+            Entry::Unchanged => *entry = Entry::Text(fs::read_to_string(file_path(uri)?)?),
+            Entry::Text(_) => (),
+        }
+        match entry {
+            Entry::Text(text) => Ok(text),
+            _ => unreachable!(),
+        }
+    }
+
+    fn exists(&mut self, uri: &Url) -> Result<bool, EditError> {
+        Ok(!matches!(self.entry(uri)?, Entry::Missing))
+    }
+
+    fn edit(
+        &mut self,
+        uri: &Url,
+        version: Option<i32>,
+        edits: &[TextEdit],
+    ) -> Result<(), EditError> {
+        if let (Some(expected), Some(actual)) = (version, self.documents.version(uri)) {
+            if expected != actual {
+                return Err(EditError::VersionMismatch {
+                    uri: uri.clone(),
+                    expected,
+                    actual,
+                });
+            }
+        }
+        let encoding = self.encoding;
+        let text = self.text(uri)?;
+        *text = apply_text_edits(text, edits, encoding)
+            .ok_or_else(|| EditError::OverlappingEdits(uri.clone()))?;
+        self.edited.insert(uri.clone());
+        Ok(())
+    }
+
+    fn document_edit(&mut self, edit: &TextDocumentEdit) -> Result<(), EditError> {
+        let edits: Vec<_> = edit
+            .edits
+            .iter()
+            .map(|edit| match edit {
+                OneOf::Left(edit) => edit.clone(),
+                OneOf::Right(annotated) => annotated.text_edit.clone(),
+            })
+            .collect();
+        let document = &edit.text_document;
+        self.edit(&document.uri, document.version, &edits)
+    }
+
+    fn resource_op(&mut self, op: &ResourceOp) -> Result<(), EditError> {
+        match op {
+            ResourceOp::Create(create) => {
+                let options = create.options.as_ref();
+                let overwrite = options.and_then(|options| options.overwrite);
+                let ignore_if_exists = options.and_then(|options| options.ignore_if_exists);
+                if self.exists(&create.uri)? && overwrite != Some(true) {
+                    if ignore_if_exists == Some(true) {
+                        return Ok(());
+                    }
+                    return Err(EditError::AlreadyExists(create.uri.clone()));
+                }
+                self.entries
+                    .insert(create.uri.clone(), Entry::Text(String::new()));
+                self.edited.remove(&create.uri);
+                self.steps.push(Step::Create(create.uri.clone()));
+            }
+            ResourceOp::Rename(rename) => {
+                let options = rename.options.as_ref();
+                let overwrite = options.and_then(|options| options.overwrite);
+                let ignore_if_exists = options.and_then(|options| options.ignore_if_exists);
+                if !self.exists(&rename.old_uri)? {
+                    return Err(EditError::NotFound(rename.old_uri.clone()));
+                }
+                if self.exists(&rename.new_uri)? && overwrite != Some(true) {
+                    if ignore_if_exists == Some(true) {
+                        return Ok(());
+                    }
+                    return Err(EditError::AlreadyExists(rename.new_uri.clone()));
+                }
+                // carry the text along, so that later edits see it at its new location
+                if file_path(&rename.old_uri)?.is_file() {
+                    self.text(&rename.old_uri)?;
+                }
+                let entry = mem::replace(self.entry(&rename.old_uri)?, Entry::Missing);
+                self.entries.insert(rename.new_uri.clone(), entry);
+                if self.edited.remove(&rename.old_uri) {
+                    self.edited.insert(rename.new_uri.clone());
+                }
+                self.steps
+                    .push(Step::Rename(rename.old_uri.clone(), rename.new_uri.clone()));
+            }
+            ResourceOp::Delete(delete) => {
+                let options = delete.options.as_ref();
+                if !self.exists(&delete.uri)? {
+                    if options.and_then(|options| options.ignore_if_not_exists) == Some(true) {
+                        return Ok(());
+                    }
+                    return Err(EditError::NotFound(delete.uri.clone()));
+                }
+                self.entries.insert(delete.uri.clone(), Entry::Missing);
+                self.edited.remove(&delete.uri);
+                self.steps.push(Step::Delete {
+                    uri: delete.uri.clone(),
+                    recursive: options.and_then(|options| options.recursive) == Some(true),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the contents of `path` with `text` all at once, by writing a temporary file
+/// beside it and renaming that over it.
+fn write_atomically(path: &Path, text: &str) -> io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.lsp-client-edit", name));
+    fs::write(&temp, text)?;
+    let replaced = match fs::metadata(path) {
+        Ok(metadata) => fs::set_permissions(&temp, metadata.permissions()),
+        Err(_) => Ok(()),
+    }
+    .and_then(|()| fs::rename(&temp, path));
+    if replaced.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    replaced
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+/// Applies `WorkspaceEdit`s to the documents open in a [`DocumentStore`], and to files on
+/// disk.
+///
+/// Text edits to open documents change the document, and are sent to the server like any
+/// other change; saving them is left to the caller. Edits to other files are written to
+/// disk, each file being replaced as a whole once all of its edits have been applied.
+///
+/// An edit is checked in full before anything is changed, so one that fails because of a
+/// version mismatch, overlapping edits or a missing file leaves everything as it was.
+/// Renaming or deleting an open document closes it, and a renamed document is reopened at
+/// its new location.
+///
+/// The editor also answers the server's `workspace/applyEdit` requests, replacing any
+/// handler already registered for them.
+pub struct WorkspaceEditor<W: Write> {
+    documents: DocumentStore<W>,
+}
+
+impl<W: Write> Clone for WorkspaceEditor<W> {
+    fn clone(&self) -> Self {
+        WorkspaceEditor {
+            documents: self.documents.clone(),
+        }
+    }
+}
+
+impl<W: 'static + Write + Send> WorkspaceEditor<W> {
+    pub fn new(documents: DocumentStore<W>) -> Self {
+        let weak = documents.downgrade();
+        documents
+            .server()
+            .on_typed_request::<ApplyWorkspaceEdit, _>(move |params| {
+                let applied = match weak.upgrade() {
+                    Some(documents) => WorkspaceEditor { documents }.apply(&params.edit),
+                    None => return Err(ResponseError::internal_error("no workspace editor")),
+                };
+                Ok(ApplyWorkspaceEditResponse {
+                    applied: applied.is_ok(),
+                    failure_reason: applied.err().map(|err| err.to_string()),
+                    failed_change: None,
+                })
+            });
+        WorkspaceEditor { documents }
+    }
+
+    pub fn documents(&self) -> &DocumentStore<W> {
+        &self.documents
+    }
+
+    /// Applies `edit`. When the edit has `documentChanges`, its `changes` are ignored.
+    pub fn apply(&self, edit: &WorkspaceEdit) -> Result<(), EditError> {
+        let mut plan = Plan {
+            documents: &self.documents,
+            encoding: self.documents.server().position_encoding(),
+            entries: HashMap::new(),
+            steps: Vec::new(),
+            edited: HashSet::new(),
+        };
+        match (&edit.document_changes, &edit.changes) {
+            (Some(DocumentChanges::Edits(edits)), _) => {
+                for edit in edits {
+                    plan.document_edit(edit)?;
+                }
+            }
+            (Some(DocumentChanges::Operations(operations)), _) => {
+                for operation in operations {
+                    match operation {
+                        DocumentChangeOperation::Edit(edit) => plan.document_edit(edit)?,
+                        DocumentChangeOperation::Op(op) => plan.resource_op(op)?,
+                    }
+                }
+            }
+            (None, Some(changes)) => {
+                for (uri, edits) in changes {
+                    plan.edit(uri, None, edits)?;
+                }
+            }
+            (None, None) => (),
+        }
+        let Plan {
+            steps,
+            edited,
+            mut entries,
+            ..
+        } = plan;
+
+        for step in steps {
+            self.perform(step)?;
+        }
+        for uri in edited {
+            let text = match entries.remove(&uri) {
+                Some(Entry::Text(text)) => text,
+                _ => continue,
+            };
+            if self.documents.is_open(&uri) {
+                self.documents.set_text(&uri, text)?;
+            } else {
+                write_atomically(&file_path(&uri)?, &text)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn perform(&self, step: Step) -> Result<(), EditError> {
+        match step {
+            Step::Create(uri) => {
+                let path = file_path(&uri)?;
+                create_parent(&path)?;
+                fs::write(path, "")?;
+            }
+            Step::Rename(old_uri, new_uri) => {
+                let (old_path, new_path) = (file_path(&old_uri)?, file_path(&new_uri)?);
+                let document = self.documents.get(&old_uri);
+                if old_path.exists() {
+                    create_parent(&new_path)?;
+                    fs::rename(old_path, new_path)?;
+                }
+                if let Some(document) = document {
+                    self.documents.close(&old_uri)?;
+                    if self.documents.is_open(&new_uri) {
+                        self.documents.close(&new_uri)?;
+                    }
+                    let text = document.text().to_owned();
+                    self.documents.open(new_uri, document.language_id(), text)?;
+                }
+            }
+            Step::Delete { uri, recursive } => {
+                let path = file_path(&uri)?;
+                if self.documents.is_open(&uri) {
+                    self.documents.close(&uri)?;
+                }
+                match fs::metadata(&path) {
+                    Ok(metadata) if metadata.is_dir() && recursive => fs::remove_dir_all(path)?,
+                    Ok(metadata) if metadata.is_dir() => fs::remove_dir(path)?,
+                    Ok(_) => fs::remove_file(path)?,
+                    // an open document that was never saved
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use lsp_types::{
+        CreateFile, DeleteFile, OptionalVersionedTextDocumentIdentifier, Position, Range,
+        RenameFile,
+    };
+
+    use super::*;
+    use crate::client::tests::SharedBuffer;
+    use crate::client::LanguageServerRef;
+
+    fn text_edit(start: (u32, u32), end: (u32, u32), text: &str) -> TextEdit {
+        TextEdit::new(
+            Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1)),
+            text.to_owned(),
+        )
+    }
+
+    fn document_edit(
+        uri: &Url,
+        version: Option<i32>,
+        edits: Vec<TextEdit>,
+    ) -> DocumentChangeOperation {
+        DocumentChangeOperation::Edit(TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version,
+            },
+            edits: edits.into_iter().map(OneOf::Left).collect(),
+        })
+    }
+
+    /// A fresh directory for each test.
+    fn temp_dir() -> PathBuf {
+        static NEXT_DIR: AtomicUsize = AtomicUsize::new(1);
+        let dir = std::env::temp_dir().join(format!(
+            "lsp-client-edit-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_apply_text_edits() {
+        let text = "let x = 1;\nlet y = 2;\n";
+        let edits = [
+            text_edit((1, 4), (1, 5), "z"),
+            text_edit((0, 0), (0, 0), "// a\n"),
+            text_edit((0, 0), (0, 0), "// b\n"),
+            text_edit((0, 8), (0, 9), "10"),
+        ];
+        assert_eq!(
+            apply_text_edits(text, &edits, PositionEncoding::Utf16).unwrap(),
+            "// a\n// b\nlet x = 10;\nlet z = 2;\n"
+        );
+        let overlapping = [text_edit((0, 0), (0, 5), ""), text_edit((0, 4), (1, 0), "")];
+        assert!(apply_text_edits(text, &overlapping, PositionEncoding::Utf16).is_none());
+    }
+
+    #[test]
+    fn test_workspace_edit() {
+        let dir = temp_dir();
+        let uri = |name: &str| Url::from_file_path(dir.join(name)).unwrap();
+        fs::write(dir.join("disk.rs"), "fn old() {}\n").unwrap();
+        fs::write(dir.join("moved.rs"), "mod moved;\n").unwrap();
+        fs::write(dir.join("gone.rs"), "").unwrap();
+        fs::write(dir.join("open.rs"), "").unwrap();
+
+        let lang_server = LanguageServerRef::new(SharedBuffer::default(), None);
+        let documents = DocumentStore::new(lang_server);
+        documents
+            .open(uri("open.rs"), "rust", "fn old() {}\n".into())
+            .unwrap();
+        let editor = WorkspaceEditor::new(documents.clone());
+
+        let rename = |old: &str, new: &str| {
+            DocumentChangeOperation::Op(ResourceOp::Rename(RenameFile {
+                old_uri: uri(old),
+                new_uri: uri(new),
+                options: None,
+                annotation_id: None,
+            }))
+        };
+        let operations = vec![
+            document_edit(
+                &uri("open.rs"),
+                Some(1),
+                vec![text_edit((0, 3), (0, 6), "new")],
+            ),
+            document_edit(
+                &uri("disk.rs"),
+                None,
+                vec![text_edit((0, 3), (0, 6), "new")],
+            ),
+            DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+                uri: uri("sub/created.rs"),
+                options: None,
+                annotation_id: None,
+            })),
+            document_edit(
+                &uri("sub/created.rs"),
+                None,
+                vec![text_edit((0, 0), (0, 0), "// new\n")],
+            ),
+            rename("moved.rs", "renamed.rs"),
+            document_edit(
+                &uri("renamed.rs"),
+                None,
+                vec![text_edit((0, 4), (0, 9), "renamed")],
+            ),
+            DocumentChangeOperation::Op(ResourceOp::Delete(DeleteFile {
+                uri: uri("gone.rs"),
+                options: None,
+            })),
+        ];
+        let edit = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(operations)),
+            ..Default::default()
+        };
+        editor.apply(&edit).unwrap();
+
+        let open = documents.get(&uri("open.rs")).unwrap();
+        assert_eq!((open.version(), open.text()), (2, "fn new() {}\n"));
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("open.rs").len(), 0, "open documents are not saved");
+        assert_eq!(read("disk.rs"), "fn new() {}\n");
+        assert_eq!(read("sub/created.rs"), "// new\n");
+        assert_eq!(read("renamed.rs"), "mod renamed;\n");
+        assert!(!dir.join("moved.rs").exists());
+        assert!(!dir.join("gone.rs").exists());
+
+        // a stale version fails the whole edit before anything changes
+        let operations = vec![
+            document_edit(
+                &uri("disk.rs"),
+                None,
+                vec![text_edit((0, 0), (0, 0), "// x\n")],
+            ),
+            document_edit(
+                &uri("open.rs"),
+                Some(1),
+                vec![text_edit((0, 0), (0, 0), "// x\n")],
+            ),
+        ];
+        let edit = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(operations)),
+            ..Default::default()
+        };
+        assert!(matches!(
+            editor.apply(&edit),
+            Err(EditError::VersionMismatch {
+                expected: 1,
+                actual: 2,
+                ..
+            })
+        ));
+        assert_eq!(read("disk.rs"), "fn new() {}\n");
+
+        // renaming an open document reopens it at its new location
+        let edit = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(vec![rename(
+                "open.rs", "moved.rs",
+            )])),
+            ..Default::default()
+        };
+        editor.apply(&edit).unwrap();
+        assert!(!documents.is_open(&uri("open.rs")));
+        assert_eq!(
+            documents.get(&uri("moved.rs")).unwrap().text(),
+            "fn new() {}\n"
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_apply_edit_requests() {
+        let buffer = SharedBuffer::default();
+        let lang_server = LanguageServerRef::new(buffer.clone(), None);
+        let documents = DocumentStore::new(lang_server.clone());
+        let uri = Url::parse("file:///open.rs").unwrap();
+        documents
+            .open(uri.clone(), "rust", "fn old() {}\n".into())
+            .unwrap();
+        let _editor = WorkspaceEditor::new(documents.clone());
+        assert!(lang_server.handles_request("workspace/applyEdit"));
+
+        let apply_edit = |id: u32, version: i32| {
+            let edit = WorkspaceEdit {
+                document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+                    text_document: OptionalVersionedTextDocumentIdentifier {
+                        uri: uri.clone(),
+                        version: Some(version),
+                    },
+                    edits: vec![OneOf::Left(text_edit((0, 3), (0, 6), "new"))],
+                }])),
+                ..Default::default()
+            };
+            let request = json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "workspace/applyEdit",
+                "params": {"edit": edit},
+            });
+            lang_server.handle_msg(&request).unwrap();
+            buffer.messages().pop().unwrap()
+        };
+        let response = apply_edit(1, 1);
+        assert_eq!(response["result"], json!({"applied": true}));
+        assert_eq!(documents.get(&uri).unwrap().text(), "fn new() {}\n");
+
+        let response = apply_edit(2, 1);
+        assert_eq!(response["result"]["applied"], false);
+        assert!(response["result"]["failureReason"]
+            .as_str()
+            .unwrap()
+            .contains("version 1"));
+    }
+}